@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS portfolio_snapshots (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME NOT NULL,
+    proxy_address VARCHAR(255) NOT NULL,
+    portfolio_total DECIMAL(20, 6) NOT NULL,
+    usdc_balance DECIMAL(20, 6) NOT NULL,
+    positions_value DECIMAL(20, 6) NOT NULL,
+    INDEX idx_timestamp (timestamp),
+    INDEX idx_proxy_address (proxy_address)
+);
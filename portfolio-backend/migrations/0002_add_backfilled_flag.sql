@@ -0,0 +1,2 @@
+-- 标记由 /api/admin/backfill 补齐的快照，图表据此区分真实数据
+ALTER TABLE portfolio_snapshots ADD COLUMN backfilled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS wallets (
+    wallet_id VARCHAR(64) PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    proxy_address VARCHAR(255) NOT NULL,
+    UNIQUE INDEX idx_wallets_proxy_address (proxy_address)
+);
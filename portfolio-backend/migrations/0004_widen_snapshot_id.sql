@@ -0,0 +1,2 @@
+-- INT 自增主键约 21 亿行后溢出，改为 BIGINT
+ALTER TABLE portfolio_snapshots MODIFY id BIGINT NOT NULL AUTO_INCREMENT;
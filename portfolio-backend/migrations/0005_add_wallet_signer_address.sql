@@ -0,0 +1 @@
+ALTER TABLE wallets ADD COLUMN signer_address VARCHAR(255) NULL;
@@ -0,0 +1 @@
+ALTER TABLE wallets ADD COLUMN display_currency VARCHAR(8) NULL, ADD COLUMN locale VARCHAR(16) NULL;
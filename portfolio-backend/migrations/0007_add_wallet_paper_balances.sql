@@ -0,0 +1,4 @@
+ALTER TABLE wallets
+    ADD COLUMN paper BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN paper_usdc_balance DOUBLE NULL,
+    ADD COLUMN paper_positions_value DOUBLE NULL;
@@ -0,0 +1 @@
+ALTER TABLE wallets ADD COLUMN label VARCHAR(255) NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS config_changes (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME(3) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    target VARCHAR(255) NOT NULL,
+    actor VARCHAR(64) NULL,
+    detail TEXT NOT NULL,
+    INDEX idx_config_changes_timestamp (timestamp)
+);
@@ -0,0 +1,10 @@
+-- 每个快照中各代币的余额，只在配置了 EXTRA_TOKENS 时写入
+CREATE TABLE IF NOT EXISTS token_balances (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    snapshot_id BIGINT NOT NULL,
+    token_address VARCHAR(42) NOT NULL,
+    symbol VARCHAR(32) NOT NULL,
+    balance DECIMAL(38, 18) NOT NULL,
+    INDEX idx_token_balances_snapshot (snapshot_id),
+    CONSTRAINT fk_token_balances_snapshot FOREIGN KEY (snapshot_id) REFERENCES portfolio_snapshots (id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE wallets ADD COLUMN cost_basis DOUBLE NULL;
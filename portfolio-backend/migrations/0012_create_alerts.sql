@@ -0,0 +1,10 @@
+-- 钱包价值变化超过 ALERT_THRESHOLD_PCT 的记录，无论 webhook 是否处于冷却期都会写入
+CREATE TABLE IF NOT EXISTS alerts (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME(3) NOT NULL,
+    proxy_address VARCHAR(255) NOT NULL,
+    old_value DECIMAL(20, 6) NOT NULL,
+    new_value DECIMAL(20, 6) NOT NULL,
+    pct_change DECIMAL(20, 4) NOT NULL,
+    INDEX idx_alerts_timestamp (timestamp)
+);
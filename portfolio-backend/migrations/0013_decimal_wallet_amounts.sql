@@ -0,0 +1,5 @@
+-- 模拟钱包余额和投入成本与快照金额一样使用定点小数，避免 DOUBLE 的精度误差
+ALTER TABLE wallets
+    MODIFY COLUMN paper_usdc_balance DECIMAL(20, 6) NULL,
+    MODIFY COLUMN paper_positions_value DECIMAL(20, 6) NULL,
+    MODIFY COLUMN cost_basis DECIMAL(20, 6) NULL;
@@ -0,0 +1,71 @@
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::SharedState;
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 按采样率均匀地决定是否记录：第 n 个请求在 floor(n * rate) 递增时被记录
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    let n = SAMPLE_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+    (n as f64 * rate).floor() > ((n - 1) as f64 * rate).floor()
+}
+
+fn next_request_id() -> String {
+    let n = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}", chrono::Utc::now().timestamp_millis(), n)
+}
+
+// 访问日志：记录 method/path/status/耗时/request id，支持采样和按路径排除；5xx 响应始终记录
+pub async fn access_log(
+    State(state): State<SharedState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(next_request_id);
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert("x-request-id", value.clone());
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+
+    let settings = &state.settings;
+    let status = response.status();
+    let excluded = settings.access_log_exclude.iter().any(|p| p == &path);
+    if settings.access_log && (status.is_server_error() || (!excluded && sampled(settings.access_log_sample_rate))) {
+        tracing::info!(
+            target: "access",
+            method = %method,
+            path = %path,
+            status = status.as_u16(),
+            duration_ms = started.elapsed().as_millis() as u64,
+            request_id = %request_id,
+            "request"
+        );
+    }
+
+    response
+}
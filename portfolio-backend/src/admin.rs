@@ -0,0 +1,286 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::audit;
+use crate::auth::Actor;
+use crate::config::{self, WalletConfig};
+use crate::db;
+use crate::error::AppError;
+use crate::SharedState;
+
+// 新增或更新钱包；WALLETS_SOURCE=db 时同时写入数据库，重启后依然生效
+pub async fn upsert_wallet(
+    State(state): State<SharedState>,
+    Extension(actor): Extension<Actor>,
+    Json(mut wallet): Json<WalletConfig>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    wallet.proxy_address = match config::normalize_address(&wallet.proxy_address) {
+        Ok(address) => address,
+        // 模拟钱包的地址只作为 key 使用，不要求是合法地址
+        Err(_) if wallet.paper => wallet.proxy_address.trim().to_string(),
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })));
+        }
+    };
+
+    if let Some(signer) = wallet.signer_address.as_deref() {
+        wallet.signer_address = match config::normalize_address(signer) {
+            Ok(address) => Some(address),
+            Err(e) => {
+                return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })));
+            }
+        };
+    }
+
+    if let Err(e) = wallet.validate() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })));
+    }
+
+    if state.settings.wallets_from_db {
+        if let Err(e) = db::upsert_wallet(&state.db_pool, &wallet).await {
+            tracing::error!("保存钱包 {} 失败: {}", wallet.wallet_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": e.to_string() })));
+        }
+    }
+
+    let before = {
+        let mut wallets = state.wallets.write().await;
+        match wallets.iter_mut().find(|w| w.wallet_id == wallet.wallet_id) {
+            Some(existing) => Some(std::mem::replace(existing, wallet.clone())),
+            None => {
+                wallets.push(wallet.clone());
+                None
+            }
+        }
+    };
+    tracing::info!("已更新钱包配置: {} ({})", wallet.wallet_id, wallet.proxy_address);
+    audit::record(&state, "wallet_upsert", &wallet.wallet_id, Some(&actor.0), serde_json::json!({
+        "before": before,
+        "after": wallet
+    })).await;
+
+    (StatusCode::OK, Json(serde_json::json!(wallet)))
+}
+
+pub async fn delete_wallet(
+    State(state): State<SharedState>,
+    Extension(actor): Extension<Actor>,
+    Path(wallet_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if state.settings.wallets_from_db {
+        if let Err(e) = db::delete_wallet(&state.db_pool, &wallet_id).await {
+            tracing::error!("删除钱包 {} 失败: {}", wallet_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": e.to_string() })));
+        }
+    }
+
+    let removed = {
+        let mut wallets = state.wallets.write().await;
+        let Some(index) = wallets.iter().position(|w| w.wallet_id == wallet_id) else {
+            return (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": format!("钱包 {} 不存在", wallet_id) })));
+        };
+        wallets.remove(index)
+    };
+    tracing::info!("已删除钱包配置: {}", wallet_id);
+    audit::record(&state, "wallet_delete", &wallet_id, Some(&actor.0), serde_json::json!({ "before": removed })).await;
+
+    (StatusCode::OK, Json(serde_json::json!({ "deleted": wallet_id })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PaperBalance {
+    pub usdc_balance: Decimal,
+    pub positions_value: Decimal,
+}
+
+// 设置模拟钱包的余额，下次刷新时生效
+pub async fn set_paper_balance(
+    State(state): State<SharedState>,
+    Extension(actor): Extension<Actor>,
+    Path(wallet_id): Path<String>,
+    Json(balance): Json<PaperBalance>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let (before, wallet) = {
+        let mut wallets = state.wallets.write().await;
+        let Some(wallet) = wallets.iter_mut().find(|w| w.wallet_id == wallet_id) else {
+            return (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": format!("钱包 {} 不存在", wallet_id) })));
+        };
+        if !wallet.paper {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": format!("钱包 {} 不是模拟钱包", wallet_id) })));
+        }
+        let before = (wallet.paper_usdc_balance, wallet.paper_positions_value);
+        wallet.paper_usdc_balance = Some(balance.usdc_balance);
+        wallet.paper_positions_value = Some(balance.positions_value);
+        (before, wallet.clone())
+    };
+
+    if state.settings.wallets_from_db {
+        if let Err(e) = db::upsert_wallet(&state.db_pool, &wallet).await {
+            tracing::error!("保存模拟钱包 {} 余额失败: {}", wallet_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": e.to_string() })));
+        }
+    }
+    tracing::info!("已设置模拟钱包 {} 余额", wallet_id);
+    audit::record(&state, "paper_balance", &wallet_id, Some(&actor.0), serde_json::json!({
+        "before": { "usdc_balance": before.0, "positions_value": before.1 },
+        "after": { "usdc_balance": balance.usdc_balance, "positions_value": balance.positions_value }
+    })).await;
+
+    (StatusCode::OK, Json(serde_json::json!(wallet)))
+}
+
+// 暂停后刷新时跳过该钱包，读接口继续返回暂停前的数据并标记 paused
+pub async fn pause_wallet(
+    State(state): State<SharedState>,
+    Extension(actor): Extension<Actor>,
+    Path(wallet_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    set_paused(&state, &actor, wallet_id, true).await
+}
+
+pub async fn resume_wallet(
+    State(state): State<SharedState>,
+    Extension(actor): Extension<Actor>,
+    Path(wallet_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    set_paused(&state, &actor, wallet_id, false).await
+}
+
+async fn set_paused(
+    state: &SharedState,
+    actor: &Actor,
+    wallet_id: String,
+    paused: bool,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if !state.wallets.read().await.iter().any(|w| w.wallet_id == wallet_id) {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": format!("钱包 {} 不存在", wallet_id) })));
+    }
+
+    let changed = {
+        let mut set = state.paused.write().await;
+        if paused {
+            set.insert(wallet_id.clone())
+        } else {
+            set.remove(&wallet_id)
+        }
+    };
+    if changed {
+        tracing::info!("钱包 {} 的刷新已{}", wallet_id, if paused { "暂停" } else { "恢复" });
+        let action = if paused { "wallet_pause" } else { "wallet_resume" };
+        audit::record(state, action, &wallet_id, Some(&actor.0), serde_json::json!({ "paused": paused })).await;
+    }
+
+    (StatusCode::OK, Json(serde_json::json!({ "wallet_id": wallet_id, "paused": paused })))
+}
+
+pub fn check_result(name: &str, started: std::time::Instant, result: Result<(), AppError>) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "passed": result.is_ok(),
+        "duration_ms": started.elapsed().as_millis() as u64,
+        "error": result.err().map(|e| e.to_string())
+    })
+}
+
+// 依次检查数据库、RPC 和 data-api；无论成败都返回 200，结果写在响应体里
+pub async fn selftest(State(state): State<SharedState>) -> Json<serde_json::Value> {
+    let service = &state.portfolio;
+    let mut checks = Vec::new();
+
+    let started = std::time::Instant::now();
+    checks.push(check_result("database", started, db::selftest_roundtrip(&state.db_pool).await));
+
+    let started = std::time::Instant::now();
+    checks.push(check_result("rpc", started, service.check_rpc().await.map(|_| ())));
+
+    let started = std::time::Instant::now();
+    checks.push(check_result("data_api", started, service.check_data_api().await));
+
+    let passed = checks.iter().all(|c| c["passed"] == true);
+    if !passed {
+        tracing::warn!("自检未通过: {:?}", checks);
+    }
+
+    Json(serde_json::json!({
+        "passed": passed,
+        "checks": checks
+    }))
+}
+
+// 运行时诊断信息：当前链、RPC 节点、启动预热、最近一次缓存与数据库一致性检查的结果、实时推送的订阅情况、快照重试队列、链上 CTF 核对
+pub async fn diagnostics(State(state): State<SharedState>) -> Json<serde_json::Value> {
+    let consistency = state.consistency.read().await.clone();
+    Json(serde_json::json!({
+        "chain": state.settings.chain.name(),
+        "rpc": state.portfolio.rpc_status(),
+        "warmup": {
+            "done": state.warmed_up.load(std::sync::atomic::Ordering::Relaxed),
+            "refresh": state.settings.warmup_refresh,
+            "concurrency": state.settings.warmup_concurrency
+        },
+        "consistency": {
+            "enabled": state.settings.consistency_check_secs > 0,
+            "interval_secs": state.settings.consistency_check_secs,
+            "tolerance": state.settings.consistency_tolerance,
+            "resync": state.settings.consistency_resync,
+            "last_check": consistency
+        },
+        "live": {
+            "batch_window_ms": state.settings.live_batch_window_ms,
+            "subscribers": state.live.subscriber_count()
+        },
+        "save_retries": {
+            "enabled": state.save_retries.enabled(),
+            "depth": state.save_retries.depth(),
+            "dropped": state.save_retries.dropped(),
+            "capacity": state.settings.save_retry_queue_size,
+            "max_attempts": state.settings.save_retry_max
+        },
+        "ctf_verification": {
+            "enabled": state.settings.ctf_verify,
+            "wallets": state.ctf_reports.read().await.values().cloned().collect::<Vec<_>>()
+        }
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErrorsQuery {
+    limit: Option<usize>,
+}
+
+// 最近遇到的错误，最新的在前
+pub async fn recent_errors(
+    State(state): State<SharedState>,
+    Query(query): Query<ErrorsQuery>,
+) -> Json<serde_json::Value> {
+    let limit = query.limit.unwrap_or(state.settings.error_log_size);
+    Json(serde_json::json!({
+        "capacity": state.settings.error_log_size,
+        "errors": state.errors.recent(limit)
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfigHistoryQuery {
+    limit: Option<usize>,
+}
+
+// 配置变更记录，最新的在前；CONFIG_HISTORY_DB 开启时从数据库读取，不受内存保留条数限制
+pub async fn config_history(
+    State(state): State<SharedState>,
+    Query(query): Query<ConfigHistoryQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let limit = query.limit.unwrap_or(state.settings.config_history_size);
+    let changes = if state.settings.config_history_db {
+        db::get_config_changes(&state.db_pool, limit as i64).await?
+    } else {
+        state.config_history.recent(limit)
+    };
+    Ok(Json(serde_json::json!({
+        "source": if state.settings.config_history_db { "db" } else { "memory" },
+        "changes": changes
+    })))
+}
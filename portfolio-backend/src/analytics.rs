@@ -0,0 +1,620 @@
+use axum::extract::{Query, State};
+use axum::Json;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use utoipa::IntoParams;
+
+use crate::db;
+use crate::error::AppError;
+use crate::history;
+use crate::response::{CompareResponse, ComparePoint, PortfolioSummary, WalletComparison, WalletSummary};
+use crate::SharedState;
+
+const YEAR_MS: f64 = 365.0 * 86_400_000.0;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BetaQuery {
+    hours: Option<i64>,
+    // 对齐序列用的分组粒度 minute/hour/day，默认 hour
+    bucket: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DrawdownQuery {
+    hours: Option<i64>,
+    // 计算合计价值时对齐各钱包的分组粒度，默认 minute
+    bucket: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TwrQuery {
+    hours: Option<i64>,
+    // 相邻快照间隔超过该值时视为数据缺口，默认取 TWR_MAX_GAP_MINUTES
+    max_gap_minutes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CompareQuery {
+    // 毫秒时间戳
+    from: i64,
+    to: i64,
+    // 最近的快照与请求时间相差超过该值时视为该端没有数据，默认取 TWR_MAX_GAP_MINUTES
+    max_gap_minutes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SummaryQuery {
+    // 从钱包列表和最高 / 最低中去掉总价值低于 MIN_PORTFOLIO_VALUE 的钱包，合计值不受影响
+    #[serde(default)]
+    hide_dust: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimeseriesQuery {
+    hours: Option<i64>,
+    // 返回的点数，默认 200，最多 MAX_TIMESERIES_POINTS
+    points: Option<usize>,
+}
+
+const MAX_TIMESERIES_POINTS: usize = 1000;
+
+// 按钱包分组的 (时间戳, portfolio_total) 序列，时间升序；补齐的快照是推算值，不参与收益计算。
+// 金额保持 Decimal，只有收益率、beta 这类无量纲的统计量才换算成 f64
+fn wallet_series(snapshots: Vec<db::PortfolioSnapshot>) -> BTreeMap<String, Vec<(i64, Decimal)>> {
+    let mut series: BTreeMap<String, Vec<(i64, Decimal)>> = BTreeMap::new();
+    for snapshot in snapshots.into_iter().filter(|s| !s.backfilled) {
+        series.entry(snapshot.proxy_address.clone()).or_default().push((
+            snapshot.timestamp.timestamp_millis(),
+            snapshot.portfolio_total,
+        ));
+    }
+    for points in series.values_mut() {
+        points.sort_by_key(|(ts, _)| *ts);
+    }
+    series
+}
+
+// TWR 用的单个快照：总价值及其中的 USDC 余额和持仓价值，用来识别充值和提现
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FlowPoint {
+    ts: i64,
+    total: Decimal,
+    usdc: Decimal,
+    positions: Decimal,
+}
+
+// 与 wallet_series 相同，但保留 USDC 余额和持仓价值
+fn wallet_flow_series(snapshots: Vec<db::PortfolioSnapshot>) -> BTreeMap<String, Vec<FlowPoint>> {
+    let mut series: BTreeMap<String, Vec<FlowPoint>> = BTreeMap::new();
+    for snapshot in snapshots.into_iter().filter(|s| !s.backfilled) {
+        series.entry(snapshot.proxy_address.clone()).or_default().push(FlowPoint {
+            ts: snapshot.timestamp.timestamp_millis(),
+            total: snapshot.portfolio_total,
+            usdc: snapshot.usdc_balance,
+            positions: snapshot.positions_value,
+        });
+    }
+    for points in series.values_mut() {
+        points.sort_by_key(|p| p.ts);
+    }
+    series
+}
+
+// 子区间内的外部资金流：持仓价值基本不变（变化 <= epsilon）而 USDC 余额变化超过 epsilon 时，
+// 这部分变化只能来自充值（正）或提现（负）；买卖和结算会同时改变两者，价格波动只改变持仓价值，都不算资金流。
+// 同一子区间内既有资金流又有持仓价格变化时识别不出来，资金流会被计入收益
+fn cash_flow(start: &FlowPoint, end: &FlowPoint, epsilon: Decimal) -> Decimal {
+    let usdc_change = end.usdc - start.usdc;
+    if (end.positions - start.positions).abs() <= epsilon && usdc_change.abs() > epsilon {
+        usdc_change
+    } else {
+        Decimal::ZERO
+    }
+}
+
+// 时间加权收益率：把序列切成相邻快照之间的子区间，资金流视为发生在子区间末尾，
+// 子区间收益 r_i = (V_i - CF_i) / V_{i-1} - 1，TWR = Π(1 + r_i) - 1，因此充值和提现本身不影响收益率。
+// 只链接间隔不超过 max_gap 的子区间：
+// - 间隔超过 max_gap 的子区间视为缺口，不参与链接，缺口前后的收益分别计入；
+// - 起点价值 <= 0 的子区间无法计算收益率，同样跳过；
+// - annualized = (1 + TWR)^(一年 / 参与链接的子区间总时长) - 1，覆盖时长为 0 时为 null；
+// - net_flows 为参与链接的子区间中识别出的资金流合计。
+fn time_weighted_return(points: &[FlowPoint], max_gap_ms: i64, flow_epsilon: Decimal) -> serde_json::Value {
+    let mut growth = 1.0;
+    let mut covered_ms = 0i64;
+    let mut periods = 0usize;
+    let mut net_flows = Decimal::ZERO;
+    let mut flow_count = 0usize;
+    let mut gaps = Vec::new();
+
+    for pair in points.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+        let duration = end.ts - start.ts;
+        if duration > max_gap_ms {
+            gaps.push(serde_json::json!({ "from": start.ts, "to": end.ts }));
+            continue;
+        }
+        if start.total <= Decimal::ZERO {
+            continue;
+        }
+        let flow = cash_flow(start, end, flow_epsilon);
+        if !flow.is_zero() {
+            net_flows += flow;
+            flow_count += 1;
+        }
+        growth *= ((end.total - flow) / start.total).to_f64().unwrap_or(1.0);
+        covered_ms += duration;
+        periods += 1;
+    }
+
+    let twr = growth - 1.0;
+    let annualized = (covered_ms > 0).then(|| growth.powf(YEAR_MS / covered_ms as f64) - 1.0);
+
+    serde_json::json!({
+        "twr": twr,
+        "annualized": annualized,
+        "periods": periods,
+        "covered_ms": covered_ms,
+        "net_flows": net_flows,
+        "flow_count": flow_count,
+        "gaps": gaps,
+        "from": points.first().map(|p| p.ts),
+        "to": points.last().map(|p| p.ts),
+    })
+}
+
+// 把各钱包的序列按分组对齐：每个分组取该钱包在组内最后一个值，组内没有数据时沿用上一个分组的值；
+// 在所有钱包都出现之前的分组被丢弃，保证每个分组上都有完整的截面
+// 返回 (钱包地址, [(分组起点, 各钱包的值)])
+type AlignedRows = Vec<(i64, Vec<Decimal>)>;
+
+fn align_series(series: &BTreeMap<String, Vec<(i64, Decimal)>>, bucket: history::Bucket) -> (Vec<String>, AlignedRows) {
+    let addresses: Vec<String> = series.keys().cloned().collect();
+    let mut by_bucket: BTreeMap<i64, Vec<Option<Decimal>>> = BTreeMap::new();
+    for (i, points) in series.values().enumerate() {
+        for (ts, value) in points {
+            by_bucket.entry(bucket.start_of(*ts)).or_insert_with(|| vec![None; addresses.len()])[i] = Some(*value);
+        }
+    }
+
+    let mut last: Vec<Option<Decimal>> = vec![None; addresses.len()];
+    let mut rows = Vec::new();
+    for (ts, values) in by_bucket {
+        for (slot, value) in last.iter_mut().zip(values) {
+            if value.is_some() {
+                *slot = value;
+            }
+        }
+        if let Some(row) = last.iter().copied().collect::<Option<Vec<Decimal>>>() {
+            rows.push((ts, row));
+        }
+    }
+    (addresses, rows)
+}
+
+// 相邻分组的简单收益率，起点价值 <= 0 时该期记为 None
+fn period_returns(values: &[Decimal]) -> Vec<Option<f64>> {
+    values.windows(2).map(|w| (w[0] > Decimal::ZERO).then(|| (w[1] / w[0] - Decimal::ONE).to_f64()).flatten()).collect()
+}
+
+// beta = cov(r_wallet, r_total) / var(r_total)，只使用两者收益率都有效的期；
+// 有效期数少于 2 或组合收益率方差为 0 时无法计算，返回 None
+fn wallet_beta(wallet: &[Option<f64>], total: &[Option<f64>]) -> (Option<f64>, usize) {
+    let pairs: Vec<(f64, f64)> = wallet.iter().zip(total).filter_map(|(w, t)| Some(((*w)?, (*t)?))).collect();
+    let n = pairs.len();
+    if n < 2 {
+        return (None, n);
+    }
+    let mean_w = pairs.iter().map(|(w, _)| w).sum::<f64>() / n as f64;
+    let mean_t = pairs.iter().map(|(_, t)| t).sum::<f64>() / n as f64;
+    let cov = pairs.iter().map(|(w, t)| (w - mean_w) * (t - mean_t)).sum::<f64>() / (n - 1) as f64;
+    let var = pairs.iter().map(|(_, t)| (t - mean_t).powi(2)).sum::<f64>() / (n - 1) as f64;
+    ((var > 0.0).then(|| cov / var), n)
+}
+
+// 每个钱包相对于全部钱包合计价值的 beta，用于找出组合波动主要来自哪些钱包
+#[utoipa::path(get, path = "/api/portfolio/beta", params(BetaQuery), responses((status = 200, description = "每个钱包的 beta")))]
+pub async fn beta(
+    State(state): State<SharedState>,
+    Query(query): Query<BetaQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let hours = query.hours.unwrap_or(168);
+    let bucket = match query.bucket.as_deref() {
+        Some(value) => history::Bucket::parse(Some(value)),
+        None => history::Bucket::Hour,
+    };
+
+    let policy = state.settings.history_invalid_timestamps;
+    let snapshots: Vec<_> = db::get_history(&state.db_pool, hours, None)
+        .await?
+        .into_iter()
+        .filter_map(|s| history::sanitize_snapshot(s, policy))
+        .collect();
+
+    let (addresses, rows) = align_series(&wallet_series(snapshots), bucket);
+    let totals: Vec<Decimal> = rows.iter().map(|(_, row)| row.iter().sum()).collect();
+    let total_returns = period_returns(&totals);
+
+    let wallets: Vec<_> = addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| {
+            let values: Vec<Decimal> = rows.iter().map(|(_, row)| row[i]).collect();
+            let (beta, periods) = wallet_beta(&period_returns(&values), &total_returns);
+            serde_json::json!({
+                "proxy_address": address,
+                "beta": beta,
+                "periods": periods
+            })
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "hours": hours,
+        "bucket_ms": bucket.millis(),
+        "buckets": rows.len(),
+        "wallets": wallets
+    })))
+}
+
+// 最大回撤，单次遍历：维护到目前为止的最高点 peak，每个点计算相对 peak 的回落 peak - value，
+// 回落比例超过当前最大值时记录这一对 (peak, trough)。单调不减的序列回撤为 0，peak 和 trough 都为 null。
+// 比例按 (peak - trough) / peak 计算，peak <= 0 时只比较绝对回落
+fn max_drawdown(points: &[(i64, Decimal)]) -> serde_json::Value {
+    // (ratio, drop) 越大回撤越深
+    let severity = |peak: Decimal, trough: Decimal| {
+        let drop = peak - trough;
+        (if peak > Decimal::ZERO { drop / peak } else { Decimal::ZERO }, drop)
+    };
+
+    let mut peak: Option<(i64, Decimal)> = None;
+    let mut worst: Option<((i64, Decimal), (i64, Decimal))> = None;
+
+    for &(ts, value) in points {
+        match peak {
+            Some(current) if value < current.1 => {
+                let is_worse = match worst {
+                    Some((p, t)) => severity(current.1, value) > severity(p.1, t.1),
+                    None => true,
+                };
+                if is_worse {
+                    worst = Some((current, (ts, value)));
+                }
+            }
+            Some(current) if value == current.1 => {}
+            _ => peak = Some((ts, value)),
+        }
+    }
+
+    match worst {
+        Some(((peak_ts, peak_value), (trough_ts, trough_value))) => serde_json::json!({
+            "max_drawdown": peak_value - trough_value,
+            "max_drawdown_pct": (severity(peak_value, trough_value).0 * Decimal::ONE_HUNDRED).round_dp(4),
+            "peak": { "timestamp": peak_ts, "value": peak_value },
+            "trough": { "timestamp": trough_ts, "value": trough_value }
+        }),
+        None => serde_json::json!({
+            "max_drawdown": Decimal::ZERO,
+            "max_drawdown_pct": Decimal::ZERO,
+            "peak": null,
+            "trough": null
+        }),
+    }
+}
+
+// 每个钱包以及全部钱包合计价值的最大回撤
+#[utoipa::path(get, path = "/api/portfolio/drawdown", params(DrawdownQuery), responses((status = 200, description = "最大回撤及其峰值和谷值")))]
+pub async fn drawdown(
+    State(state): State<SharedState>,
+    Query(query): Query<DrawdownQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let hours = query.hours.unwrap_or(720);
+    let bucket = history::Bucket::parse(query.bucket.as_deref());
+
+    let policy = state.settings.history_invalid_timestamps;
+    let snapshots: Vec<_> = db::get_history(&state.db_pool, hours, None)
+        .await?
+        .into_iter()
+        .filter_map(|s| history::sanitize_snapshot(s, policy))
+        .collect();
+
+    let series = wallet_series(snapshots);
+    let (_, rows) = align_series(&series, bucket);
+    let totals: Vec<(i64, Decimal)> = rows.iter().map(|(ts, row)| (*ts, row.iter().sum())).collect();
+
+    let wallets: Vec<_> = series
+        .iter()
+        .map(|(address, points)| {
+            let mut entry = max_drawdown(points);
+            entry["proxy_address"] = serde_json::json!(address);
+            entry
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "hours": hours,
+        "overall": max_drawdown(&totals),
+        "wallets": wallets
+    })))
+}
+
+// 把时间窗口等分为 points 段，每段末尾取一次全部钱包的合计价值，无论窗口多长都返回 points 个点。
+// 缺口不做线性插值：段内没有新快照的钱包沿用它之前最后一个快照的值（阶梯式），
+// 这样的点 filled 为 true；窗口开头还没有任何快照的段 total 为 null
+#[utoipa::path(get, path = "/api/portfolio/timeseries", params(TimeseriesQuery), responses((status = 200, description = "等间隔的合计价值序列")))]
+pub async fn timeseries(
+    State(state): State<SharedState>,
+    Query(query): Query<TimeseriesQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let hours = query.hours.unwrap_or(24).max(1);
+    let points = query.points.unwrap_or(200).clamp(1, MAX_TIMESERIES_POINTS);
+
+    let policy = state.settings.history_invalid_timestamps;
+    let mut snapshots: Vec<_> = db::get_history(&state.db_pool, hours, None)
+        .await?
+        .into_iter()
+        .filter_map(|s| history::sanitize_snapshot(s, policy))
+        .collect();
+    snapshots.sort_by_key(|s| s.timestamp);
+
+    let end = chrono::Utc::now().timestamp_millis();
+    let start = end - hours * 3_600_000;
+    let step = (end - start) as f64 / points as f64;
+
+    let mut latest: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut remaining = snapshots.iter().peekable();
+    let series: Vec<_> = (1..=points)
+        .map(|i| {
+            let timestamp = start + (step * i as f64).round() as i64;
+            let mut fresh = false;
+            while let Some(snapshot) = remaining.next_if(|s| s.timestamp.timestamp_millis() <= timestamp) {
+                latest.insert(snapshot.proxy_address.clone(), snapshot.portfolio_total);
+                fresh = true;
+            }
+            let total = (!latest.is_empty()).then(|| latest.values().sum::<Decimal>());
+            serde_json::json!({
+                "timestamp": timestamp,
+                "total": total,
+                "filled": total.is_some() && !fresh
+            })
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "hours": hours,
+        "points": points,
+        "step_ms": step.round() as i64,
+        "series": series
+    })))
+}
+
+#[utoipa::path(get, path = "/api/portfolio/twr", params(TwrQuery), responses((status = 200, description = "每个钱包的时间加权收益率")))]
+pub async fn twr(
+    State(state): State<SharedState>,
+    Query(query): Query<TwrQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let hours = query.hours.unwrap_or(168);
+    let max_gap_ms = query.max_gap_minutes.unwrap_or(state.settings.twr_max_gap_minutes).max(1) * 60_000;
+
+    let policy = state.settings.history_invalid_timestamps;
+    let snapshots: Vec<_> = db::get_history(&state.db_pool, hours, None)
+        .await?
+        .into_iter()
+        .filter_map(|s| history::sanitize_snapshot(s, policy))
+        .collect();
+
+    let flow_epsilon = state.settings.twr_flow_epsilon;
+    let wallets: Vec<_> = wallet_flow_series(snapshots)
+        .into_iter()
+        .map(|(address, points)| {
+            let mut entry = time_weighted_return(&points, max_gap_ms, flow_epsilon);
+            entry["proxy_address"] = serde_json::json!(address);
+            entry
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "hours": hours,
+        "max_gap_minutes": max_gap_ms / 60_000,
+        "flow_epsilon": flow_epsilon,
+        "wallets": wallets
+    })))
+}
+
+fn change_pct(current: Decimal, past: Decimal) -> Option<Decimal> {
+    (past > Decimal::ZERO).then(|| ((current - past) / past * Decimal::ONE_HUNDRED).round_dp(4))
+}
+
+// 每个钱包在 from 和 to 两个时间点附近最近的快照及其差值
+#[utoipa::path(get, path = "/api/portfolio/compare", params(CompareQuery), responses(
+    (status = 200, body = CompareResponse),
+    (status = 400, description = "时间戳无效"),
+))]
+pub async fn compare(
+    State(state): State<SharedState>,
+    Query(query): Query<CompareQuery>,
+) -> Result<Json<CompareResponse>, AppError> {
+    let parse = |ms: i64| {
+        chrono::DateTime::<chrono::Utc>::from_timestamp_millis(ms)
+            .ok_or_else(|| AppError::ParseError(format!("无效的时间戳: {}", ms)))
+    };
+    let (from, to) = (parse(query.from)?, parse(query.to)?);
+    let max_gap = chrono::Duration::minutes(query.max_gap_minutes.unwrap_or(state.settings.twr_max_gap_minutes).max(1));
+
+    let nearest = |address: String, at: chrono::DateTime<chrono::Utc>| {
+        let pool = &state.db_pool;
+        async move {
+            let point = db::get_snapshot_nearest(pool, &address, at)
+                .await?
+                .filter(|s| (s.timestamp - at).abs() <= max_gap)
+                .map(|s| ComparePoint { timestamp: s.timestamp.timestamp_millis(), portfolio_total: s.portfolio_total });
+            Ok::<_, AppError>(point)
+        }
+    };
+
+    let mut wallets = Vec::new();
+    let (mut total_from, mut total_to) = (Decimal::ZERO, Decimal::ZERO);
+    for wallet in state.wallets().await {
+        let start = nearest(wallet.proxy_address.clone(), from).await?;
+        let end = nearest(wallet.proxy_address.clone(), to).await?;
+        let (delta, delta_pct) = match (&start, &end) {
+            (Some(start), Some(end)) => {
+                total_from += start.portfolio_total;
+                total_to += end.portfolio_total;
+                (Some(end.portfolio_total - start.portfolio_total), change_pct(end.portfolio_total, start.portfolio_total))
+            }
+            _ => (None, None),
+        };
+        wallets.push(WalletComparison {
+            proxy_address: wallet.proxy_address,
+            name: wallet.name,
+            from: start,
+            to: end,
+            delta,
+            delta_pct,
+        });
+    }
+
+    Ok(Json(CompareResponse {
+        from: query.from,
+        to: query.to,
+        total_from,
+        total_to,
+        delta: total_to - total_from,
+        delta_pct: change_pct(total_to, total_from),
+        wallets,
+    }))
+}
+
+// 总价值、24 小时变化、相对 cost_basis 的未实现盈亏以及价值最高和最低的钱包。当前值来自缓存（为空时用数据库最新快照），
+// 24 小时前的值取该时刻之前最近的一条快照，早于该时刻超过 TWR_MAX_GAP_MINUTES 的视为没有数据
+#[utoipa::path(get, path = "/api/portfolio/summary", params(SummaryQuery), responses(
+    (status = 200, body = PortfolioSummary, description = "合计始终包含零头钱包，hide_dust 只从 wallets 和 highest / lowest 中去掉它们"),
+))]
+pub async fn summary(
+    State(state): State<SharedState>,
+    Query(query): Query<SummaryQuery>,
+) -> Result<Json<PortfolioSummary>, AppError> {
+    let wallets = state.wallets().await;
+    let mut current = state.cache.read().await.clone();
+    if current.is_empty() {
+        for snapshot in db::get_latest_snapshots(&state.db_pool).await? {
+            current.insert(snapshot.proxy_address.clone(), snapshot.to_portfolio_data());
+        }
+    }
+
+    let now = chrono::Utc::now();
+    let at = now - chrono::Duration::hours(24);
+    let oldest = at - chrono::Duration::minutes(state.settings.twr_max_gap_minutes.max(1));
+
+    let tracked: Vec<_> = wallets.iter().filter_map(|w| current.get(&w.proxy_address).cloned()).collect();
+    let inactive = crate::inactive_wallets(&state, &tracked).await;
+
+    let mut summaries = Vec::new();
+    let (mut current_sum, mut past_sum) = (Decimal::ZERO, Decimal::ZERO);
+    let (mut pnl_value_sum, mut cost_sum, mut has_cost) = (Decimal::ZERO, Decimal::ZERO, false);
+    for wallet in &wallets {
+        let Some(data) = current.get(&wallet.proxy_address) else {
+            continue;
+        };
+        let past = db::get_snapshot_at(&state.db_pool, &wallet.proxy_address, at)
+            .await?
+            .filter(|s| s.timestamp >= oldest)
+            .map(|s| s.portfolio_total);
+        if let Some(past) = past {
+            current_sum += data.portfolio_total;
+            past_sum += past;
+        }
+        let cost_basis = wallet.cost_basis;
+        if let Some(cost) = cost_basis {
+            pnl_value_sum += data.portfolio_total;
+            cost_sum += cost;
+            has_cost = true;
+        }
+        summaries.push(WalletSummary {
+            proxy_address: wallet.proxy_address.clone(),
+            name: wallet.name.clone(),
+            portfolio_total: data.portfolio_total,
+            positions_count: data.positions_count,
+            change_24h: past.map(|p| data.portfolio_total - p),
+            change_24h_pct: past.and_then(|p| change_pct(data.portfolio_total, p)),
+            inactive: inactive.contains(&wallet.proxy_address),
+            dust: state.is_dust(data.portfolio_total),
+            pnl: cost_basis.map(|cost| data.portfolio_total - cost),
+            pnl_pct: cost_basis.and_then(|cost| change_pct(data.portfolio_total, cost)),
+        });
+    }
+
+    let has_change = summaries.iter().any(|w| w.change_24h.is_some());
+    let total_portfolio = summaries.iter().map(|w| w.portfolio_total).sum();
+    let total_positions_count = summaries.iter().filter_map(|w| w.positions_count).reduce(|a, b| a + b);
+    // 合计在隐藏零头钱包之前计算，hide_dust 只影响列表
+    let before = summaries.len();
+    if query.hide_dust {
+        summaries.retain(|w| !w.dust);
+    }
+    let dust_hidden = before - summaries.len();
+    let pick = |better: fn(Decimal, Decimal) -> bool| {
+        summaries
+            .iter()
+            .reduce(|a, b| if better(b.portfolio_total, a.portfolio_total) { b } else { a })
+            .cloned()
+    };
+
+    Ok(Json(PortfolioSummary {
+        total_portfolio,
+        wallet_count: wallets.len(),
+        total_positions_count,
+        change_24h: has_change.then_some(current_sum - past_sum),
+        change_24h_pct: if has_change { change_pct(current_sum, past_sum) } else { None },
+        pnl: has_cost.then_some(pnl_value_sum - cost_sum),
+        pnl_pct: if has_cost { change_pct(pnl_value_sum, cost_sum) } else { None },
+        highest: pick(|a, b| a > b),
+        lowest: pick(|a, b| a < b),
+        wallets: summaries,
+        dust_hidden,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(ts: i64, usdc: i64, positions: i64) -> FlowPoint {
+        let (usdc, positions) = (Decimal::from(usdc), Decimal::from(positions));
+        FlowPoint { ts, total: usdc + positions, usdc, positions }
+    }
+
+    #[test]
+    fn twr_excludes_deposits_and_withdrawals() {
+        let epsilon = Decimal::new(1, 2);
+        // 持仓从 100 涨到 110（+10%），之后充值 500、再提现 200，持仓不变
+        let points = [point(0, 100, 100), point(60_000, 100, 110), point(120_000, 600, 110), point(180_000, 400, 110)];
+        let result = time_weighted_return(&points, 3_600_000, epsilon);
+
+        let twr = result["twr"].as_f64().unwrap();
+        assert!((twr - 0.05).abs() < 1e-9, "twr = {}", twr);
+        assert_eq!(result["net_flows"], "300");
+        assert_eq!(result["flow_count"], 2);
+        assert_eq!(result["periods"], 3);
+    }
+
+    #[test]
+    fn twr_skips_gaps() {
+        let points = [point(0, 100, 0), point(60_000, 110, 10), point(10_000_000, 220, 20)];
+        let result = time_weighted_return(&points, 3_600_000, Decimal::new(1, 2));
+
+        let twr = result["twr"].as_f64().unwrap();
+        assert!((twr - 0.2).abs() < 1e-9, "twr = {}", twr);
+        assert_eq!(result["periods"], 1);
+        assert_eq!(result["gaps"].as_array().unwrap().len(), 1);
+    }
+}
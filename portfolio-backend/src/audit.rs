@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::db;
+use crate::SharedState;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    pub timestamp: i64,
+    // 例如 wallet_upsert、wallet_delete、paper_balance、wallet_pause
+    pub action: String,
+    // 受影响的对象，目前都是 wallet_id
+    pub target: String,
+    pub actor: Option<String>,
+    // 变更内容，通常包含 before / after
+    pub detail: serde_json::Value,
+}
+
+// 最近的配置变更记录，超过容量时丢弃最旧的；CONFIG_HISTORY_DB 开启时同时写入 config_changes 表
+pub struct ConfigHistory {
+    capacity: usize,
+    entries: Mutex<VecDeque<ConfigChange>>,
+}
+
+impl ConfigHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn push(&self, change: ConfigChange) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(change);
+    }
+
+    // 最新的在前
+    pub fn recent(&self, limit: usize) -> Vec<ConfigChange> {
+        self.entries.lock().unwrap().iter().rev().take(limit).cloned().collect()
+    }
+}
+
+pub async fn record(
+    state: &SharedState,
+    action: &str,
+    target: &str,
+    actor: Option<&str>,
+    detail: serde_json::Value,
+) {
+    let change = ConfigChange {
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        action: action.to_string(),
+        target: target.to_string(),
+        actor: actor.map(str::to_string),
+        detail,
+    };
+    tracing::info!(target: "audit", action = %change.action, target_id = %change.target, actor = ?change.actor, "配置变更");
+
+    if state.settings.config_history_db {
+        if let Err(e) = db::save_config_change(&state.db_pool, &change).await {
+            tracing::error!("保存配置变更记录失败: {}", e);
+            state.errors.record("config_history", None, &e);
+        }
+    }
+    state.config_history.push(change);
+}
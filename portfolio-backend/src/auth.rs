@@ -0,0 +1,63 @@
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use crate::SharedState;
+
+// 通过鉴权的调用方，写入请求扩展供审计记录使用；目前只有一个管理密钥，统一记为 admin
+#[derive(Debug, Clone)]
+pub struct Actor(pub String);
+
+// 管理接口鉴权：要求 Authorization: Bearer <ADMIN_API_KEY>，未配置 ADMIN_API_KEY 时管理接口整体禁用
+pub async fn require_admin(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = state.settings.admin_api_key.as_deref() else {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "未配置 ADMIN_API_KEY，管理接口已禁用" })),
+        ).into_response();
+    };
+
+    if bearer_token(&request) != Some(expected) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "未授权" })),
+        ).into_response();
+    }
+
+    let mut request = request;
+    request.extensions_mut().insert(Actor("admin".to_string()));
+    next.run(request).await
+}
+
+// 读接口鉴权：配置了 API_KEY 时要求 Authorization: Bearer <API_KEY>，未配置时直接放行
+pub async fn require_api_key(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(expected) = state.settings.api_key.as_deref() {
+        if bearer_token(&request) != Some(expected) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "error": "未授权" })),
+            ).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+fn bearer_token(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
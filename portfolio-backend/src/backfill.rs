@@ -0,0 +1,164 @@
+use axum::extract::State;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::db;
+use crate::error::AppError;
+use crate::SharedState;
+
+// 单个钱包一次最多补齐的点数，防止误操作写入海量数据
+const MAX_POINTS_PER_WALLET: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    // 沿用缺口前最后一个快照的值
+    CarryForward,
+    // 在缺口前后两个快照之间按时间线性插值，缺口后没有数据时退化为 CarryForward
+    Linear,
+}
+
+impl Strategy {
+    pub fn parse(value: &str) -> Option<Strategy> {
+        match value.trim().to_lowercase().as_str() {
+            "carry_forward" | "carry-forward" => Some(Strategy::CarryForward),
+            "linear" => Some(Strategy::Linear),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BackfillRequest {
+    pub from: i64,
+    pub to: i64,
+    pub strategy: Option<String>,
+    pub interval_secs: Option<i64>,
+    // 必须等于 "<from>-<to>"，用来显式确认补齐的时间范围
+    pub confirm: String,
+}
+
+// 快照的三个金额：portfolio_total, usdc_balance, positions_value
+type Values = [Decimal; 3];
+
+fn snapshot_values(snapshot: &db::PortfolioSnapshot) -> Values {
+    [snapshot.portfolio_total, snapshot.usdc_balance, snapshot.positions_value]
+}
+
+// 在 (from, to) 之间按 step 生成补齐点，不包含两个端点
+fn fill_points(
+    before: (i64, Values),
+    after: Option<(i64, Values)>,
+    from: i64,
+    to: i64,
+    step: i64,
+    strategy: Strategy,
+) -> Vec<(i64, Values)> {
+    let mut points = Vec::new();
+    let mut ts = from + step;
+
+    while ts < to {
+        let values = match (strategy, after) {
+            (Strategy::Linear, Some((after_ts, after_values))) if after_ts > before.0 => {
+                // 插值结果按数据库列的精度保留 6 位小数
+                let ratio = Decimal::from(ts - before.0) / Decimal::from(after_ts - before.0);
+                let mut values = [Decimal::ZERO; 3];
+                for i in 0..3 {
+                    values[i] = (before.1[i] + (after_values[i] - before.1[i]) * ratio).round_dp(6);
+                }
+                values
+            }
+            _ => before.1,
+        };
+        points.push((ts, values));
+        ts += step;
+    }
+
+    points
+}
+
+pub async fn backfill(
+    State(state): State<SharedState>,
+    Json(request): Json<BackfillRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    // 请求参数错误按 ParseError 返回 400
+    if request.confirm != format!("{}-{}", request.from, request.to) {
+        return Err(AppError::ParseError(format!("confirm 必须等于 \"{}-{}\"", request.from, request.to)));
+    }
+
+    let strategy_name = request.strategy.as_deref().unwrap_or(&state.settings.backfill_strategy);
+    let Some(strategy) = Strategy::parse(strategy_name) else {
+        return Err(AppError::ParseError(format!("未知的补齐策略: {}", strategy_name)));
+    };
+
+    let interval_secs = request.interval_secs.unwrap_or(state.settings.backfill_interval_secs);
+    if interval_secs <= 0 {
+        return Err(AppError::ParseError("interval_secs 必须大于 0".to_string()));
+    }
+    let step = interval_secs
+        .checked_mul(1000)
+        .ok_or_else(|| AppError::ParseError(format!("interval_secs 过大: {}", interval_secs)))?;
+
+    let (Some(from), Some(to)) = (
+        DateTime::<Utc>::from_timestamp_millis(request.from),
+        DateTime::<Utc>::from_timestamp_millis(request.to),
+    ) else {
+        return Err(AppError::ParseError("无效的时间范围".to_string()));
+    };
+    if from >= to || to > Utc::now() {
+        return Err(AppError::ParseError("时间范围必须满足 from < to <= 当前时间".to_string()));
+    }
+    if (request.to - request.from) / step > MAX_POINTS_PER_WALLET {
+        return Err(AppError::ParseError(format!("补齐点数超过上限 {}，请增大 interval_secs", MAX_POINTS_PER_WALLET)));
+    }
+
+    let mut results = Vec::new();
+
+    for wallet in &state.wallets().await {
+        let address = &wallet.proxy_address;
+
+        let outcome = async {
+            // 缺口内已有真实数据的钱包不做补齐
+            if db::count_snapshots_between(&state.db_pool, address, from, to).await? > 0 {
+                return Ok::<_, AppError>(serde_json::json!({ "skipped": "缺口内已有快照" }));
+            }
+            let Some(before) = db::get_snapshot_at(&state.db_pool, address, from).await? else {
+                return Ok(serde_json::json!({ "skipped": "缺口前没有快照" }));
+            };
+            let after = db::get_snapshot_at_or_after(&state.db_pool, address, to).await?;
+
+            let points = fill_points(
+                (before.timestamp.timestamp_millis(), snapshot_values(&before)),
+                after.as_ref().map(|s| (s.timestamp.timestamp_millis(), snapshot_values(s))),
+                request.from,
+                request.to,
+                step,
+                strategy,
+            );
+            db::save_backfilled_snapshots(&state.db_pool, address, &points).await?;
+
+            Ok(serde_json::json!({ "inserted": points.len() }))
+        }.await;
+
+        let mut entry = match outcome {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::error!("钱包 {} 补齐快照失败: {}", wallet.name, e);
+                state.errors.record("backfill", Some(address), &e);
+                serde_json::json!({ "error": e.to_string() })
+            }
+        };
+        entry["proxy_address"] = serde_json::json!(address);
+        results.push(entry);
+    }
+
+    tracing::info!("完成快照补齐: {} ~ {}, 策略 {:?}", from, to, strategy);
+
+    Ok(Json(serde_json::json!({
+        "from": request.from,
+        "to": request.to,
+        "strategy": strategy_name,
+        "wallets": results
+    })))
+}
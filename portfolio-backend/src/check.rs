@@ -0,0 +1,65 @@
+use std::time::Instant;
+
+use crate::admin::check_result;
+use crate::config::{self, Settings};
+use crate::db;
+use crate::error::AppError;
+use crate::labels;
+
+// 检查模式：依次检查数据库、钱包配置、RPC、data-api 和 ENS RPC（若开启），把报告打印到标准输出，全部通过时返回 true。
+// 不执行数据库迁移，也不发送告警 webhook
+pub async fn run(settings: &Settings) -> bool {
+    let mut checks = Vec::new();
+
+    let started = Instant::now();
+    let connected = async {
+        let pool = db::create_pool(settings).await?;
+        db::ping(&pool).await?;
+        Ok::<_, AppError>(pool)
+    }.await;
+    let pool = match connected {
+        Ok(pool) => {
+            checks.push(check_result("database", started, Ok(())));
+            Some(pool)
+        }
+        Err(e) => {
+            checks.push(check_result("database", started, Err(e)));
+            None
+        }
+    };
+
+    let started = Instant::now();
+    let wallets = crate::load_wallets(settings, pool.as_ref()).await;
+    let count = wallets.as_ref().map_or(0, Vec::len);
+    let result = wallets.and_then(|wallets| {
+        if wallets.is_empty() && config::env_flag("REQUIRE_WALLETS") {
+            return Err(AppError::ParseError("REQUIRE_WALLETS 已开启且没有钱包配置".to_string()));
+        }
+        Ok(())
+    });
+    let mut wallets_check = check_result("wallets", started, result);
+    wallets_check["count"] = serde_json::json!(count);
+    checks.push(wallets_check);
+
+    let service = crate::build_portfolio_service(settings);
+
+    let started = Instant::now();
+    checks.push(check_result("rpc", started, service.check_rpc().await.map(|_| ())));
+
+    let started = Instant::now();
+    checks.push(check_result("data_api", started, service.check_data_api().await));
+
+    if let Some(rpc_url) = settings.ens_rpc_url.as_deref().filter(|_| settings.ens_lookup) {
+        let started = Instant::now();
+        checks.push(check_result("ens_rpc", started, labels::check_ens(rpc_url).await));
+    }
+
+    if let Some(pool) = pool {
+        pool.close().await;
+    }
+
+    let passed = checks.iter().all(|c| c["passed"] == true);
+    let report = serde_json::json!({ "passed": passed, "checks": checks });
+    println!("{}", serde_json::to_string_pretty(&report).unwrap_or_else(|_| report.to_string()));
+    passed
+}
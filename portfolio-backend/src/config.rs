@@ -1,27 +1,571 @@
+use alloy::primitives::Address;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use crate::error::AppError;
+use crate::portfolio::Chain;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
 pub struct WalletConfig {
     pub wallet_id: String,
     pub name: String,
     pub proxy_address: String,
+    // 可选的签名 EOA，其 USDC 余额会计入该钱包；持仓仍只按 proxy_address 查询。配置中也可以写作 eoa_address
+    #[serde(default, alias = "eoa_address", skip_serializing_if = "Option::is_none")]
+    pub signer_address: Option<String>,
+    // 展示货币（如 EUR）和地区格式（如 de-DE），默认 USD / en-US
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    // 模拟钱包：余额手动设置，刷新时不访问链上和 data-api
+    #[serde(default)]
+    pub paper: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paper_usdc_balance: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paper_positions_value: Option<Decimal>,
+    // 展示用的可读标签
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    // 投入成本（USD），设置后 /api/portfolio/summary 计算未实现盈亏
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_basis: Option<Decimal>,
 }
 
-pub fn load_wallets_from_env() -> Vec<WalletConfig> {
+impl WalletConfig {
+    // 地址格式错误的钱包每次刷新都必然失败，加载时就排除；模拟钱包的地址只作为 key，不要求合法
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.wallet_id.trim().is_empty() {
+            return Err(AppError::ParseError("wallet_id 不能为空".to_string()));
+        }
+        if !self.paper {
+            Address::from_str(self.proxy_address.trim())
+                .map_err(|e| AppError::ParseError(format!("钱包 {} 的 proxy_address 无效 {}: {}", self.wallet_id, self.proxy_address, e)))?;
+        }
+        if let Some(signer) = self.signer_address.as_deref() {
+            Address::from_str(signer.trim())
+                .map_err(|e| AppError::ParseError(format!("钱包 {} 的 signer_address 无效 {}: {}", self.wallet_id, signer, e)))?;
+        }
+        if let Some(cost_basis) = self.cost_basis {
+            if cost_basis < Decimal::ZERO {
+                return Err(AppError::ParseError(format!("钱包 {} 的 cost_basis 无效: {}", self.wallet_id, cost_basis)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 地址无效的钱包记录警告后跳过；金额（模拟余额、cost_basis）格式错误时返回错误，
+// 避免被当成未设置而悄悄算错盈亏，检查模式也能据此报告
+pub fn load_wallets_from_env() -> Result<Vec<WalletConfig>, AppError> {
     let mut wallets = Vec::new();
     
     for i in 1..=10 {
         let key = format!("WALLET_{}_PROXY_ADDRESS", i);
-        if let Ok(proxy_address) = std::env::var(&key) {
-            if !proxy_address.is_empty() {
-                wallets.push(WalletConfig {
-                    wallet_id: i.to_string(),
-                    name: format!("钱包 {}", i),
-                    proxy_address,
-                });
+        let paper = env_flag(&format!("WALLET_{}_PAPER", i));
+        let proxy_address = match env_string(&key) {
+            Some(address) => address,
+            // 模拟钱包可以不配置地址，用 paper-{i} 作为缓存和数据库中的 key
+            None if paper => format!("paper-{}", i),
+            None => continue,
+        };
+
+        let proxy_address = match normalize_address(&proxy_address) {
+            Ok(address) => address,
+            Err(_) if paper => proxy_address,
+            Err(e) => {
+                tracing::warn!("{} 地址无效，跳过该钱包: {}", key, e);
+                continue;
             }
+        };
+        // WALLET_{i}_EOA_ADDRESS 与 WALLET_{i}_SIGNER_ADDRESS 等价，同时设置时以后者为准
+        let signer_key = [format!("WALLET_{}_SIGNER_ADDRESS", i), format!("WALLET_{}_EOA_ADDRESS", i)]
+            .into_iter()
+            .find(|key| env_string(key).is_some())
+            .unwrap_or_default();
+        let signer_address = env_string(&signer_key).and_then(|address| {
+            normalize_address(&address)
+                .map_err(|e| tracing::warn!("{} 地址无效，忽略: {}", signer_key, e))
+                .ok()
+        });
+        let wallet = WalletConfig {
+            wallet_id: i.to_string(),
+            name: format!("钱包 {}", i),
+            proxy_address,
+            signer_address,
+            display_currency: env_string(&format!("WALLET_{}_CURRENCY", i)),
+            locale: env_string(&format!("WALLET_{}_LOCALE", i)),
+            paper,
+            paper_usdc_balance: env_amount(&format!("WALLET_{}_PAPER_USDC", i))?,
+            paper_positions_value: env_amount(&format!("WALLET_{}_PAPER_POSITIONS", i))?,
+            label: env_string(&format!("WALLET_{}_LABEL", i)),
+            cost_basis: env_amount(&format!("WALLET_{}_COST_BASIS", i))?,
+        };
+        match wallet.validate() {
+            Ok(()) => wallets.push(wallet),
+            Err(e) => tracing::warn!("跳过配置无效的钱包: {}", e),
         }
     }
     
-    wallets
+    Ok(wallets)
+}
+
+// 读取金额型环境变量，未设置时为 None，无法解析时返回错误
+fn env_amount(key: &str) -> Result<Option<Decimal>, AppError> {
+    env_string(key)
+        .map(|v| v.parse::<Decimal>().map_err(|e| AppError::ParseError(format!("{}={} 不是有效的金额: {}", key, v, e))))
+        .transpose()
+}
+
+// 配置文件可以是顶层数组，也可以是 {"wallets": [...]}；TOML 只支持后者，即 [[wallets]] 表数组
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WalletsFile {
+    List(Vec<WalletConfig>),
+    Table { wallets: Vec<WalletConfig> },
+}
+
+// 按扩展名解析 TOML 或 JSON 钱包配置文件，地址必须是合法的 0x 地址（模拟钱包除外），出错时指出是第几个条目
+pub fn load_wallets_from_file(path: &Path) -> Result<Vec<WalletConfig>, AppError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| AppError::ParseError(format!("无法读取配置文件 {}: {}", path.display(), e)))?;
+
+    let is_toml = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+    let parsed: WalletsFile = if is_toml {
+        toml::from_str(&content)
+            .map_err(|e| AppError::ParseError(format!("配置文件 {} 格式错误: {}", path.display(), e)))?
+    } else {
+        serde_json::from_str(&content)
+            .map_err(|e| AppError::ParseError(format!("配置文件 {} 格式错误: {}", path.display(), e)))?
+    };
+    let mut wallets = match parsed {
+        WalletsFile::List(wallets) | WalletsFile::Table { wallets } => wallets,
+    };
+
+    for (i, wallet) in wallets.iter_mut().enumerate() {
+        let entry = format!("第 {} 个钱包（wallet_id={}）", i + 1, wallet.wallet_id);
+        let has_prefix = wallet.proxy_address.trim().starts_with("0x");
+        wallet.proxy_address = match normalize_address(&wallet.proxy_address) {
+            Ok(address) if has_prefix => address,
+            Ok(_) => {
+                return Err(AppError::ParseError(format!("配置文件 {} 中{}的 proxy_address 必须以 0x 开头", path.display(), entry)));
+            }
+            Err(_) if wallet.paper && !wallet.proxy_address.trim().is_empty() => wallet.proxy_address.trim().to_string(),
+            Err(e) => {
+                return Err(AppError::ParseError(format!("配置文件 {} 中{}的 proxy_address 无效: {}", path.display(), entry, e)));
+            }
+        };
+        if let Some(signer) = wallet.signer_address.as_deref() {
+            wallet.signer_address = Some(normalize_address(signer).map_err(|e| {
+                AppError::ParseError(format!("配置文件 {} 中{}的 signer_address 无效: {}", path.display(), entry, e))
+            })?);
+        }
+        wallet.validate()
+            .map_err(|e| AppError::ParseError(format!("配置文件 {} 中{}无效: {}", path.display(), entry, e)))?;
+    }
+
+    Ok(wallets)
+}
+
+// 运行时配置，启动时从环境变量读取一次
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub admin_api_key: Option<String>,
+    // 读接口的访问密钥，未配置时不鉴权
+    pub api_key: Option<String>,
+    // 允许跨域访问的来源（scheme://host[:port]），None 表示未配置 ALLOWED_ORIGINS，允许任意来源
+    pub allowed_origins: Option<Vec<String>>,
+    // HTTP 服务的监听地址和端口，默认 0.0.0.0:8405；BIND_ADDR=127.0.0.1 时只接受本机访问
+    pub bind_addr: String,
+    pub port: u16,
+    // CHAIN=polygon|amoy，默认 polygon 主网
+    pub chain: Chain,
+    // Polygon RPC 节点，按顺序故障切换；默认为所选链的公共节点，建议换成自己的 Alchemy/Infura 地址
+    pub polygon_rpc_urls: Vec<reqwest::Url>,
+    // DATA_API_URL 可指向 data-api 的镜像或代理，默认为官方地址
+    pub data_api_url: String,
+    // WALLETS_SOURCE=db 时从 wallets 表读取钱包，管理接口的修改也会写回数据库
+    pub wallets_from_db: bool,
+    pub approvals_check: bool,
+    pub backfill_strategy: String,
+    pub backfill_interval_secs: i64,
+    // 单次刷新可同时占用的数据库连接数，应小于连接池上限
+    pub refresh_db_concurrency: usize,
+    // 数据库连接池的最大、最小连接数和获取连接的超时（秒）
+    pub db_max_connections: u32,
+    pub db_min_connections: u32,
+    pub db_acquire_timeout_secs: u64,
+    // 开启后与该地址最新快照的三个金额相差都不超过 dedup_epsilon 时不再写入新快照；
+    // 历史数据会因此出现间隔，TWR_MAX_GAP_MINUTES 需要相应调大
+    pub dedup_snapshots: bool,
+    pub dedup_epsilon: rust_decimal::Decimal,
+    // 快照保留天数，每天清理一次更早的快照，0 表示不清理
+    pub retention_days: u32,
+    // 按订单簿深度估算可变现价值，会显著增加 CLOB 请求数，默认关闭
+    pub depth_valuation: bool,
+    // 每 1 USD 兑换目标货币的汇率，用于钱包的展示货币
+    pub fx_rates: HashMap<String, f64>,
+    // 访问日志开关、采样率（0~1）以及不记录的路径
+    pub access_log: bool,
+    pub access_log_sample_rate: f64,
+    pub access_log_exclude: Vec<String>,
+    // raw=true 历史查询最多返回的快照行数
+    pub history_raw_max_rows: usize,
+    // /api/portfolio/history 每页默认返回的快照行数及允许的最大值
+    pub history_page_size: i64,
+    pub history_max_page_size: i64,
+    // data-api /value 响应中计入持仓价值的字段，逗号分隔，默认 value
+    pub positions_value_fields: Vec<String>,
+    // 地址（小写）到标签的映射，来自 WALLET_LABELS=0xabc:主钱包,0xdef:体育
+    pub wallet_labels: HashMap<String, String>,
+    // ENS 反向解析需要主网 RPC，默认关闭
+    pub ens_lookup: bool,
+    pub ens_rpc_url: Option<String>,
+    // 内存中保留最近多少分钟的刷新结果，0 表示关闭
+    pub history_buffer_minutes: i64,
+    // 缓存与数据库一致性检查的间隔（秒），0 表示关闭
+    pub consistency_check_secs: u64,
+    pub consistency_tolerance: rust_decimal::Decimal,
+    // 发现不一致时以较新的一方为准同步另一方
+    pub consistency_resync: bool,
+    // 前端静态文件目录，设置后在 / 下提供前端页面
+    pub frontend_dir: Option<String>,
+    // 历史数据中时间戳异常的行如何处理：skip（默认）或 clamp
+    pub history_invalid_timestamps: crate::history::InvalidTimestamp,
+    // 计算时间加权收益率时，相邻快照间隔超过该分钟数视为数据缺口
+    pub twr_max_gap_minutes: i64,
+    // 相邻快照之间持仓价值变化不超过该值、USDC 余额变化超过该值时，视为一笔充值或提现
+    pub twr_flow_epsilon: rust_decimal::Decimal,
+    // 不计入持仓价值的市场 condition id，需要走逐个持仓的明细接口
+    pub excluded_markets: Vec<String>,
+    // 实时推送的合并窗口（毫秒），窗口内的多次刷新合并为一次推送，0 表示每次刷新都推送
+    pub live_batch_window_ms: u64,
+    // 统计每个钱包持有的市场数量，聚合接口下需要额外请求持仓明细
+    pub positions_count: bool,
+    // 刷新结果中附带逐个持仓的明细（市场、方向、数量、均价、当前价值），持仓价值改为明细汇总
+    pub positions_detail: bool,
+    // data-api /value 返回非 JSON 响应时按 0 处理（DATA_API_NON_JSON=zero），默认作为错误重试
+    pub non_json_as_zero: bool,
+    // data-api /value 结果的缓存时间（秒），短时间内重复刷新同一钱包时不再请求；0 表示不缓存
+    pub positions_value_cache_secs: u64,
+    // 快照写入失败后的重试次数上限，0 表示不重试
+    pub save_retry_max: u32,
+    pub save_retry_queue_size: usize,
+    pub save_retry_base_ms: u64,
+    // 用于把授权交易的 gas 费用换算成美元，未设置时只返回 POL 数额
+    pub pol_usd_price: Option<f64>,
+    // /api/admin/errors 保留的最近错误条数
+    pub error_log_size: usize,
+    // 允许客户端通过 Accept: application/msgpack 请求 MessagePack 响应
+    pub msgpack_responses: bool,
+    // 刷新后核对 data-api 持仓与链上 CTF 代币余额，每个钱包每次刷新额外一次 RPC 调用
+    pub ctf_verify: bool,
+    // 内存中保留的配置变更记录条数；CONFIG_HISTORY_DB 开启时同时写入 config_changes 表
+    pub config_history_size: usize,
+    pub config_history_db: bool,
+    // 启动时是否立即做一次完整刷新，以及这次刷新同时请求的钱包数
+    pub warmup_refresh: bool,
+    pub warmup_concurrency: usize,
+    // 刷新时同时请求的钱包数，避免把公共 RPC 打满
+    pub refresh_concurrency: usize,
+    // 后台定时刷新的间隔（秒），0 表示只在调用 /api/portfolio/refresh 时刷新
+    pub refresh_interval_secs: u64,
+    // 两次调用 /api/portfolio/refresh 的最小间隔（秒），期间再调用返回 429；0 表示不限制
+    pub refresh_cooldown_secs: u64,
+    // 缓存数据超过该秒数视为过期，0 表示不判断过期
+    pub cache_ttl_secs: u64,
+    // 最近这么多条快照的总价值都为 0 的钱包标记为 inactive（多半是地址填错了），0 表示不检查
+    pub inactive_lookback_snapshots: i64,
+    // 刷新前用 Multicall3 一次查询所有钱包的 USDC 余额，默认开启
+    pub usdc_multicall: bool,
+    // 查询 USDC 余额和持仓价值失败时的最大尝试次数（含第一次）及退避的基础等待时间
+    pub fetch_retry_attempts: u32,
+    pub fetch_retry_base_ms: u64,
+    // 外部 HTTP 请求（data-api、CLOB、RPC）的总超时和建立连接的超时（秒），以及空闲连接的保留时间和每个主机最多保留的空闲连接数
+    pub http_timeout_secs: u64,
+    pub http_connect_timeout_secs: u64,
+    pub http_pool_idle_secs: u64,
+    pub http_pool_max_idle_per_host: usize,
+    // 除 USDC 外需要查询余额的 ERC20 代币
+    pub extra_tokens: Vec<crate::portfolio::Token>,
+    // 告警 webhook 地址
+    pub webhook_url: Option<String>,
+    // 快照保存后发布到的 NATS 服务器和 subject，需要编译时开启 nats feature
+    pub nats_url: Option<String>,
+    pub nats_subject: String,
+    // 超过该秒数没有任何钱包刷新成功时告警，0 表示关闭
+    pub freshness_sla_secs: u64,
+    pub freshness_alert_cooldown_secs: u64,
+    // 单个钱包两次刷新之间总价值变化超过该百分比时告警，0 表示关闭；同一钱包在冷却时间（秒）内只告警一次
+    pub alert_threshold_pct: rust_decimal::Decimal,
+    pub alert_cooldown_secs: u64,
+    // 总价值低于该值（USD）的钱包视为零头钱包，在 cached 和 summary 中标记 dust，可用 ?hide_dust=true 隐藏；0 表示关闭
+    pub min_portfolio_value: rust_decimal::Decimal,
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        // 链选错会查到完全不同的合约，不能静默回退到默认值
+        let chain = match env_string("CHAIN") {
+            Some(value) => Chain::parse(&value).unwrap_or_else(|e| {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }),
+            None => Chain::default(),
+        };
+        Self {
+            admin_api_key: env_string("ADMIN_API_KEY"),
+            api_key: env_string("API_KEY"),
+            allowed_origins: allowed_origins(),
+            bind_addr: env_string("BIND_ADDR").unwrap_or_else(|| "0.0.0.0".to_string()),
+            port: env_parse("PORT", 8405),
+            chain,
+            polygon_rpc_urls: polygon_rpc_urls(chain),
+            data_api_url: env_string("DATA_API_URL").unwrap_or_else(|| crate::portfolio::DATA_API_URL.to_string()),
+            wallets_from_db: env_string("WALLETS_SOURCE").is_some_and(|v| v.eq_ignore_ascii_case("db")),
+            approvals_check: env_flag("APPROVALS_CHECK"),
+            backfill_strategy: env_string("BACKFILL_STRATEGY").unwrap_or_else(|| "carry_forward".to_string()),
+            backfill_interval_secs: env_parse("BACKFILL_INTERVAL_SECS", 300),
+            refresh_db_concurrency: env_parse("REFRESH_DB_CONCURRENCY", 4).max(1),
+            db_max_connections: env_parse("DB_MAX_CONNECTIONS", 10).max(1),
+            db_min_connections: env_parse("DB_MIN_CONNECTIONS", 0),
+            db_acquire_timeout_secs: env_parse("DB_ACQUIRE_TIMEOUT_SECS", 30).max(1),
+            dedup_snapshots: env_flag("DEDUP_SNAPSHOTS"),
+            dedup_epsilon: env_parse("DEDUP_EPSILON", rust_decimal::Decimal::new(1, 6)).abs(),
+            retention_days: env_parse("RETENTION_DAYS", 90),
+            depth_valuation: env_flag("DEPTH_VALUATION"),
+            fx_rates: crate::format::parse_rates(env_string("FX_RATES").as_deref()),
+            access_log: env_flag("ACCESS_LOG"),
+            access_log_sample_rate: env_parse("ACCESS_LOG_SAMPLE_RATE", 1.0),
+            access_log_exclude: match env_list("ACCESS_LOG_EXCLUDE") {
+                list if list.is_empty() => vec!["/api/health".to_string()],
+                list => list,
+            },
+            history_raw_max_rows: env_parse("HISTORY_RAW_MAX_ROWS", 5000),
+            history_page_size: env_parse("HISTORY_PAGE_SIZE", 1000).max(1),
+            history_max_page_size: env_parse("HISTORY_MAX_PAGE_SIZE", 10000).max(1),
+            positions_value_fields: env_list("POSITIONS_VALUE_FIELDS"),
+            wallet_labels: env_list("WALLET_LABELS")
+                .into_iter()
+                .filter_map(|pair| {
+                    let (address, label) = pair.split_once(':')?;
+                    Some((address.trim().to_lowercase(), label.trim().to_string()))
+                })
+                .collect(),
+            ens_lookup: env_flag("ENS_LOOKUP"),
+            ens_rpc_url: env_string("ENS_RPC_URL"),
+            history_buffer_minutes: env_parse("HISTORY_BUFFER_MINUTES", 60),
+            consistency_check_secs: env_parse("CONSISTENCY_CHECK_SECS", 0),
+            consistency_tolerance: env_parse("CONSISTENCY_TOLERANCE", rust_decimal::Decimal::new(1, 2)),
+            consistency_resync: env_flag("CONSISTENCY_RESYNC"),
+            frontend_dir: env_string("FRONTEND_DIR"),
+            history_invalid_timestamps: crate::history::InvalidTimestamp::parse(
+                env_string("HISTORY_INVALID_TIMESTAMPS").as_deref(),
+            ),
+            twr_max_gap_minutes: env_parse("TWR_MAX_GAP_MINUTES", 60),
+            twr_flow_epsilon: env_parse("TWR_FLOW_EPSILON", rust_decimal::Decimal::new(1, 2)).abs(),
+            excluded_markets: env_list("EXCLUDED_MARKETS"),
+            live_batch_window_ms: env_parse("LIVE_BATCH_WINDOW_MS", 0),
+            positions_count: env_flag("POSITIONS_COUNT"),
+            positions_detail: env_flag("POSITIONS_DETAIL"),
+            non_json_as_zero: env_string("DATA_API_NON_JSON").is_some_and(|v| v.eq_ignore_ascii_case("zero")),
+            positions_value_cache_secs: env_parse("POSITIONS_VALUE_CACHE_SECS", 30),
+            save_retry_max: env_parse("SAVE_RETRY_MAX", 5),
+            save_retry_queue_size: env_parse("SAVE_RETRY_QUEUE_SIZE", 1000),
+            save_retry_base_ms: env_parse("SAVE_RETRY_BASE_MS", 1000),
+            pol_usd_price: env_string("POL_USD_PRICE").and_then(|v| v.parse().ok()),
+            error_log_size: env_parse("ERROR_LOG_SIZE", 200),
+            msgpack_responses: env_flag_or("MSGPACK_RESPONSES", true),
+            ctf_verify: env_flag("CTF_VERIFY"),
+            config_history_size: env_parse("CONFIG_HISTORY_SIZE", 200),
+            config_history_db: env_flag("CONFIG_HISTORY_DB"),
+            warmup_refresh: env_flag_or("WARMUP_REFRESH", true),
+            warmup_concurrency: env_parse("WARMUP_CONCURRENCY", 2).max(1),
+            refresh_concurrency: env_parse("REFRESH_CONCURRENCY", 5).max(1),
+            refresh_interval_secs: env_parse("REFRESH_INTERVAL_SECS", 300),
+            refresh_cooldown_secs: env_parse("REFRESH_COOLDOWN_SECS", 10),
+            cache_ttl_secs: env_parse("CACHE_TTL_SECS", 900),
+            inactive_lookback_snapshots: env_parse("INACTIVE_LOOKBACK_SNAPSHOTS", 12).max(0),
+            usdc_multicall: env_flag_or("USDC_MULTICALL", true),
+            fetch_retry_attempts: env_parse("FETCH_RETRY_ATTEMPTS", 3).max(1),
+            fetch_retry_base_ms: env_parse("FETCH_RETRY_BASE_MS", 200),
+            http_timeout_secs: env_parse("HTTP_TIMEOUT_SECS", 10).max(1),
+            http_connect_timeout_secs: env_parse("HTTP_CONNECT_TIMEOUT_SECS", 5).max(1),
+            http_pool_idle_secs: env_parse("HTTP_POOL_IDLE_SECS", 90),
+            http_pool_max_idle_per_host: env_parse("HTTP_POOL_MAX_IDLE_PER_HOST", 16),
+            extra_tokens: crate::portfolio::parse_tokens(env_string("EXTRA_TOKENS").as_deref()),
+            webhook_url: env_string("WEBHOOK_URL"),
+            nats_url: env_string("NATS_URL"),
+            nats_subject: env_string("NATS_SUBJECT").unwrap_or_else(|| "portfolio.snapshots".to_string()),
+            freshness_sla_secs: env_parse("FRESHNESS_SLA_SECS", 0),
+            freshness_alert_cooldown_secs: env_parse("FRESHNESS_ALERT_COOLDOWN_SECS", 1800),
+            alert_threshold_pct: env_parse("ALERT_THRESHOLD_PCT", rust_decimal::Decimal::ZERO).abs(),
+            min_portfolio_value: env_parse("MIN_PORTFOLIO_VALUE", rust_decimal::Decimal::ZERO).max(rust_decimal::Decimal::ZERO),
+            alert_cooldown_secs: env_parse("ALERT_COOLDOWN_SECS", 3600),
+        }
+    }
+}
+
+// POLYGON_RPC_URLS（逗号分隔）优先，其次 POLYGON_RPC_URL，都没有或都无效时使用所选链的默认公共节点
+fn polygon_rpc_urls(chain: Chain) -> Vec<reqwest::Url> {
+    let mut urls: Vec<reqwest::Url> = env_list("POLYGON_RPC_URLS")
+        .into_iter()
+        .filter_map(|url| match url.parse() {
+            Ok(url) => Some(url),
+            Err(e) => {
+                tracing::warn!("POLYGON_RPC_URLS 中的 {} 无效，已跳过: {}", url, e);
+                None
+            }
+        })
+        .collect();
+    if urls.is_empty() {
+        if let Some(url) = env_string("POLYGON_RPC_URL") {
+            match url.parse() {
+                Ok(url) => urls.push(url),
+                Err(e) => tracing::warn!("POLYGON_RPC_URL={} 无效，使用默认节点: {}", url, e),
+            }
+        }
+    }
+    if urls.is_empty() {
+        urls.push(chain.default_rpc().parse().unwrap());
+    }
+    urls
+}
+
+// ALLOWED_ORIGINS 逗号分隔，每项必须是只含 scheme、主机和端口的 http(s) 地址，无效项跳过；
+// 配置了但全部无效时返回空列表，即不允许任何跨域来源，而不是放开为任意来源
+fn allowed_origins() -> Option<Vec<String>> {
+    env_string("ALLOWED_ORIGINS")?;
+    let origins = env_list("ALLOWED_ORIGINS")
+        .into_iter()
+        .filter_map(|origin| match parse_origin(&origin) {
+            Ok(origin) => Some(origin),
+            Err(e) => {
+                tracing::warn!("ALLOWED_ORIGINS 中的 {} 无效，已跳过: {}", origin, e);
+                None
+            }
+        })
+        .collect();
+    Some(origins)
+}
+
+fn parse_origin(origin: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(origin).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("只支持 http 和 https".to_string());
+    }
+    if url.host_str().is_none() {
+        return Err("缺少主机名".to_string());
+    }
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+        return Err("不能包含路径或参数".to_string());
+    }
+    // ascii_serialization 给出浏览器 Origin 头的格式，省略默认端口且不带结尾斜杠
+    Ok(url.origin().ascii_serialization())
+}
+
+// 读取非空字符串环境变量
+pub fn env_string(key: &str) -> Option<String> {
+    std::env::var(key).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// 读取逗号分隔的环境变量列表，忽略空项
+pub fn env_list(key: &str) -> Vec<String> {
+    env_string(key)
+        .map(|v| v.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+// 读取并解析环境变量，缺失或解析失败时使用默认值
+pub fn env_parse<T: FromStr>(key: &str, default: T) -> T {
+    match env_string(key) {
+        Some(v) => v.parse().unwrap_or_else(|_| {
+            tracing::warn!("环境变量 {}={} 解析失败，使用默认值", key, v);
+            default
+        }),
+        None => default,
+    }
+}
+
+// 读取布尔型环境变量，支持 1/true/yes/on
+pub fn env_flag(key: &str) -> bool {
+    env_flag_or(key, false)
+}
+
+// 同 env_flag，未设置时使用 default
+pub fn env_flag_or(key: &str, default: bool) -> bool {
+    match env_string(key) {
+        Some(v) => matches!(v.to_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        None => default,
+    }
+}
+
+// 地址统一的大小写格式，由 ADDRESS_CASE 配置（checksum 或 lower，默认 checksum）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressCase {
+    Checksum,
+    Lower,
+}
+
+fn address_case() -> AddressCase {
+    static CASE: OnceLock<AddressCase> = OnceLock::new();
+    *CASE.get_or_init(|| match std::env::var("ADDRESS_CASE").map(|v| v.to_lowercase()).as_deref() {
+        Ok("lower") | Ok("lowercase") => AddressCase::Lower,
+        _ => AddressCase::Checksum,
+    })
+}
+
+// 将地址转换成统一格式，保证同一个钱包在配置、缓存和数据库中只对应一个 key
+pub fn normalize_address(address: &str) -> Result<String, AppError> {
+    let parsed = Address::from_str(address.trim())
+        .map_err(|e| AppError::ParseError(format!("无效地址 {}: {}", address, e)))?;
+    Ok(match address_case() {
+        AddressCase::Checksum => parsed.to_checksum(None),
+        AddressCase::Lower => format!("{:#x}", parsed),
+    })
+}
+
+// 数据库里可能存有旧格式的地址，无法解析时保持原样
+pub fn canonical_address(address: &str) -> String {
+    normalize_address(address).unwrap_or_else(|_| address.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // EIP-55 示例地址
+    const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    #[test]
+    fn mixed_case_addresses_normalize_to_one_key() {
+        let lower = CHECKSUMMED.to_lowercase();
+        let upper = format!("0x{}", CHECKSUMMED[2..].to_uppercase());
+        for input in [CHECKSUMMED, lower.as_str(), upper.as_str(), " 0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed "] {
+            assert_eq!(normalize_address(input).unwrap(), CHECKSUMMED);
+        }
+        assert!(normalize_address("0x1234").is_err());
+        assert_eq!(canonical_address("paper-1"), "paper-1");
+    }
+
+    #[test]
+    fn wallet_file_addresses_are_normalized() {
+        let path = std::env::temp_dir().join(format!("wallets-{}.json", std::process::id()));
+        let content = format!(
+            r#"[{{"wallet_id": "1", "name": "a", "proxy_address": "{}", "signer_address": "{}"}}]"#,
+            CHECKSUMMED.to_lowercase(),
+            CHECKSUMMED.to_uppercase().replacen("0X", "0x", 1),
+        );
+        std::fs::write(&path, content).unwrap();
+        let wallets = load_wallets_from_file(&path);
+        std::fs::remove_file(&path).ok();
+
+        let wallets = wallets.unwrap();
+        assert_eq!(wallets[0].proxy_address, CHECKSUMMED);
+        assert_eq!(wallets[0].signer_address.as_deref(), Some(CHECKSUMMED));
+    }
 }
@@ -0,0 +1,141 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::db;
+use crate::portfolio::PortfolioData;
+use crate::SharedState;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    pub proxy_address: String,
+    // 缓存中有但数据库中没有任何快照时为 None
+    pub db_timestamp: Option<i64>,
+    pub cache_timestamp: i64,
+    pub fields: Vec<String>,
+    pub resynced: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub checked_at: i64,
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+    // 服务启动以来累计发现的不一致次数
+    pub total_mismatches: u64,
+    pub error: Option<String>,
+}
+
+fn differing_fields(cached: &PortfolioData, stored: &PortfolioData, tolerance: Decimal) -> Vec<String> {
+    [
+        ("portfolio_total", cached.portfolio_total, stored.portfolio_total),
+        ("usdc_balance", cached.usdc_balance, stored.usdc_balance),
+        ("positions_value", cached.positions_value, stored.positions_value),
+    ]
+    .into_iter()
+    .filter(|(_, a, b)| (a - b).abs() > tolerance)
+    .map(|(name, _, _)| name.to_string())
+    .collect()
+}
+
+// 比较每个缓存钱包和数据库中最新的快照，只记录差异；开启 resync 时以较新的一方为准
+pub async fn check(state: &SharedState) -> CheckReport {
+    let checked_at = chrono::Utc::now().timestamp_millis();
+    let previous_total = state.consistency.read().await.as_ref().map_or(0, |r| r.total_mismatches);
+
+    let latest = match db::get_latest_snapshots(&state.db_pool).await {
+        Ok(latest) => latest,
+        Err(e) => {
+            tracing::error!("一致性检查查询数据库失败: {}", e);
+            state.errors.record("consistency", None, &e);
+            return CheckReport {
+                checked_at,
+                checked: 0,
+                mismatches: Vec::new(),
+                total_mismatches: previous_total,
+                error: Some(e.to_string()),
+            };
+        }
+    };
+
+    let cached: Vec<PortfolioData> = state.cache.read().await.values().cloned().collect();
+    let tolerance = state.settings.consistency_tolerance;
+    let mut mismatches = Vec::new();
+
+    for data in &cached {
+        let stored = latest.iter().find(|s| s.proxy_address.eq_ignore_ascii_case(&data.proxy_address));
+        let stored_data = stored.map(|s| s.to_portfolio_data());
+        let fields = match &stored_data {
+            Some(stored_data) => differing_fields(data, stored_data, tolerance),
+            None => vec!["missing".to_string()],
+        };
+        if fields.is_empty() {
+            continue;
+        }
+
+        let db_timestamp = stored.map(|s| s.timestamp.timestamp_millis());
+        tracing::warn!(
+            "缓存与数据库不一致: {} 字段 {:?}, 缓存时间 {}, 数据库时间 {:?}",
+            data.proxy_address, fields, data.last_updated, db_timestamp
+        );
+
+        let mut resynced = None;
+        if state.settings.consistency_resync {
+            match (&stored_data, db_timestamp) {
+                // 数据库较新，说明缓存过期
+                (Some(stored_data), Some(ts)) if ts > data.last_updated => {
+                    let mut fresh = stored_data.clone();
+                    fresh.paper = data.paper;
+                    state.cache.write().await.insert(data.proxy_address.clone(), fresh);
+                    resynced = Some("cache");
+                }
+                // 部分数据获取失败的结果按 save_refreshed 的约定不进入历史；模拟钱包的余额是手动设置的，
+                // 由下一次刷新正常写入，两者都不回写
+                _ if data.partial || data.paper => {}
+                // 缓存较新，说明写入数据库失败；和刷新走同一条写入路径（并发限制、去重、代币余额）
+                _ => match crate::save_snapshot_bounded(state, data).await {
+                    Ok(true) => resynced = Some("db"),
+                    Ok(false) => {}
+                    Err(e) => {
+                        tracing::error!("一致性检查回写数据库失败: {}", e);
+                        state.errors.record("consistency", Some(&data.proxy_address), &e);
+                    }
+                },
+            }
+        }
+
+        mismatches.push(Mismatch {
+            proxy_address: data.proxy_address.clone(),
+            db_timestamp,
+            cache_timestamp: data.last_updated,
+            fields,
+            resynced,
+        });
+    }
+
+    CheckReport {
+        checked_at,
+        checked: cached.len(),
+        total_mismatches: previous_total + mismatches.len() as u64,
+        mismatches,
+        error: None,
+    }
+}
+
+pub fn spawn(state: SharedState) {
+    let secs = state.settings.consistency_check_secs;
+    if secs == 0 {
+        return;
+    }
+    tracing::info!("已开启缓存与数据库一致性检查，间隔 {} 秒", secs);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(secs));
+        // 第一次 tick 立即触发，此时缓存还是空的，跳过
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let report = check(&state).await;
+            *state.consistency.write().await = Some(report);
+        }
+    });
+}
@@ -0,0 +1,138 @@
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+use crate::SharedState;
+
+const MSGPACK: &str = "application/msgpack";
+
+// 根据 Accept 头选择响应格式，默认 JSON；MSGPACK_RESPONSES 关闭时始终返回 JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MsgPack,
+}
+
+impl Format {
+    pub fn respond<T: Serialize>(self, value: &T) -> Response {
+        match self {
+            Format::Json => Json(value).into_response(),
+            // 按字段名编码，客户端可以直接还原成与 JSON 相同的结构
+            Format::MsgPack => match rmp_serde::to_vec_named(value) {
+                Ok(bytes) => ([(header::CONTENT_TYPE, HeaderValue::from_static(MSGPACK))], bytes).into_response(),
+                Err(e) => {
+                    tracing::error!("MessagePack 编码失败: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            },
+        }
+    }
+}
+
+impl FromRequestParts<SharedState> for Format {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &SharedState) -> Result<Self, Self::Rejection> {
+        if !state.settings.msgpack_responses {
+            return Ok(Format::Json);
+        }
+        let accept = parts.headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+        Ok(negotiate(accept))
+    }
+}
+
+// 按 q 值比较 MessagePack 和 JSON（含 application/*、*/*），同等优先时选 MessagePack；没有 Accept 头时为 JSON
+fn negotiate(accept: Option<&str>) -> Format {
+    let Some(accept) = accept else {
+        return Format::Json;
+    };
+    let mut msgpack_q = 0.0f32;
+    let mut json_q = 0.0f32;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or("").trim();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if media.eq_ignore_ascii_case(MSGPACK) || media.eq_ignore_ascii_case("application/x-msgpack") {
+            msgpack_q = msgpack_q.max(q);
+        } else if media.eq_ignore_ascii_case("application/json") || media == "application/*" || media == "*/*" {
+            json_q = json_q.max(q);
+        }
+    }
+    if msgpack_q > 0.0 && msgpack_q >= json_q {
+        Format::MsgPack
+    } else {
+        Format::Json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::PortfolioData;
+    use crate::response::CachedResponse;
+    use rust_decimal::Decimal;
+
+    fn sample() -> CachedResponse {
+        let wallet = PortfolioData {
+            proxy_address: "0x1111111111111111111111111111111111111111".to_string(),
+            usdc_balance: Decimal::new(123_456_789, 6),
+            positions_value: Decimal::new(500_025, 2),
+            positions_count: Some(3),
+            portfolio_total: Decimal::new(623_706_789, 6),
+            last_updated: 1_700_000_000_000,
+            ..Default::default()
+        };
+        CachedResponse {
+            total_portfolio: wallet.portfolio_total,
+            total_usdc_balance: wallet.usdc_balance,
+            total_positions_value: wallet.positions_value,
+            total_positions_count: wallet.positions_count,
+            wallets: vec![wallet],
+            dust_hidden: 0,
+        }
+    }
+
+    async fn body(format: Format) -> (Option<String>, Vec<u8>) {
+        let response = format.respond(&sample());
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn json_and_msgpack_round_trip_to_the_same_value() {
+        let (json_type, json) = body(Format::Json).await;
+        let (msgpack_type, msgpack) = body(Format::MsgPack).await;
+        assert_eq!(json_type.as_deref(), Some("application/json"));
+        assert_eq!(msgpack_type.as_deref(), Some(MSGPACK));
+
+        let from_json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        let from_msgpack: serde_json::Value = rmp_serde::from_slice(&msgpack).unwrap();
+        assert_eq!(from_json, from_msgpack);
+        assert_eq!(from_json["total_portfolio"], "623.706789");
+    }
+
+    #[test]
+    fn negotiate_respects_q_values() {
+        assert_eq!(negotiate(Some("application/msgpack;q=0.9, application/json")), Format::Json);
+        assert_eq!(negotiate(Some("application/json;q=0.5, application/msgpack")), Format::MsgPack);
+        assert_eq!(negotiate(Some("application/msgpack")), Format::MsgPack);
+        assert_eq!(negotiate(Some("application/msgpack;q=0")), Format::Json);
+    }
+
+    #[test]
+    fn negotiate_defaults_to_json() {
+        assert_eq!(negotiate(None), Format::Json);
+        assert_eq!(negotiate(Some("*/*")), Format::Json);
+        assert_eq!(negotiate(Some("text/html")), Format::Json);
+    }
+}
@@ -1,12 +1,21 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum AppError {
     #[error("RPC调用失败: {0}")]
     RpcError(String),
+
+    // 无法连上 RPC 节点（网络错误、HTTP 错误、节点不可用），换一个节点可能成功
+    #[error("RPC连接失败: {0}")]
+    RpcConnectError(String),
     
+    // 第二个字段为对方返回的 HTTP 状态码，网络错误或响应内容无效时为 None
     #[error("API请求失败: {0}")]
-    ApiError(String),
+    ApiError(String, Option<u16>),
     
     #[error("解析错误: {0}")]
     ParseError(String),
@@ -14,3 +23,89 @@ pub enum AppError {
     #[error("数据库错误: {0}")]
     DbError(String),
 }
+
+impl AppError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::RpcError(_) => "RpcError",
+            AppError::RpcConnectError(_) => "RpcConnectError",
+            AppError::ApiError(..) => "ApiError",
+            AppError::ParseError(_) => "ParseError",
+            AppError::DbError(_) => "DbError",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::RpcError(_) | AppError::RpcConnectError(_) | AppError::ApiError(..) => StatusCode::BAD_GATEWAY,
+            AppError::ParseError(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn upstream_status(&self) -> Option<u16> {
+        match self {
+            AppError::ApiError(_, status) => *status,
+            _ => None,
+        }
+    }
+
+    // 解析错误和除 429 以外的 4xx 重试也不会成功
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AppError::ParseError(_) => false,
+            AppError::ApiError(_, Some(status)) => *status == 429 || !(400..500).contains(status),
+            _ => true,
+        }
+    }
+}
+
+// 区分传输层错误和调用本身的错误（如 revert），前者适合切换 RPC 节点重试
+impl From<alloy::contract::Error> for AppError {
+    fn from(e: alloy::contract::Error) -> Self {
+        use alloy::transports::RpcError;
+
+        match &e {
+            alloy::contract::Error::TransportError(RpcError::Transport(_)) => {
+                AppError::RpcConnectError(format!("{}", e))
+            }
+            _ => AppError::RpcError(format!("{}", e)),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(serde_json::json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::transports::{RpcError, TransportErrorKind};
+
+    #[test]
+    fn transport_failures_are_connect_errors() {
+        let refused = alloy::contract::Error::TransportError(TransportErrorKind::custom_str("connection refused"));
+        let http = alloy::contract::Error::TransportError(TransportErrorKind::http_error(503, "Service Unavailable".to_string()));
+        for e in [refused, http] {
+            let err = AppError::from(e);
+            assert!(matches!(err, AppError::RpcConnectError(_)), "{:?}", err);
+            assert!(err.is_retryable());
+            assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        }
+    }
+
+    // 节点有响应但调用本身失败，换节点也没有用
+    #[test]
+    fn call_failures_are_rpc_errors() {
+        let null = alloy::contract::Error::TransportError(RpcError::NullResp);
+        let unknown = alloy::contract::Error::UnknownFunction("balanceOf".to_string());
+        for e in [null, unknown] {
+            let err = AppError::from(e);
+            assert!(matches!(err, AppError::RpcError(_)), "{:?}", err);
+            assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub timestamp: i64,
+    // 出错的位置，例如 refresh、history
+    pub context: &'static str,
+    pub wallet: Option<String>,
+    pub kind: &'static str,
+    pub status: u16,
+    pub message: String,
+}
+
+// 服务最近遇到的错误，容量固定，写满后丢弃最旧的记录
+pub struct ErrorLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<ErrorRecord>>,
+}
+
+impl ErrorLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, context: &'static str, wallet: Option<&str>, error: &AppError) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(ErrorRecord {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            context,
+            wallet: wallet.map(str::to_string),
+            kind: error.kind(),
+            status: error.status_code().as_u16(),
+            message: error.to_string(),
+        });
+    }
+
+    // 最新的在前
+    pub fn recent(&self, limit: usize) -> Vec<ErrorRecord> {
+        self.entries.lock().unwrap().iter().rev().take(limit).cloned().collect()
+    }
+}
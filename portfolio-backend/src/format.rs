@@ -0,0 +1,116 @@
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::WalletConfig;
+use crate::portfolio::PortfolioData;
+
+pub const DEFAULT_CURRENCY: &str = "USD";
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+// 按钱包配置的展示货币和地区格式化后的金额，原始数值仍保留在 PortfolioData 中
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DisplayValues {
+    pub currency: String,
+    pub locale: String,
+    pub portfolio_total: String,
+    pub usdc_balance: String,
+    pub positions_value: String,
+}
+
+// 地区对应的千分位分隔符、小数点以及货币符号是否后置
+fn locale_rules(locale: &str) -> (char, char, bool) {
+    match locale.split(['-', '_']).next().unwrap_or("").to_lowercase().as_str() {
+        "de" | "es" | "it" | "nl" | "pt" => ('.', ',', true),
+        "fr" | "ru" => ('\u{202f}', ',', true),
+        _ => (',', '.', false),
+    }
+}
+
+fn currency_symbol(currency: &str) -> Option<&'static str> {
+    match currency {
+        "USD" => Some("$"),
+        "EUR" => Some("€"),
+        "GBP" => Some("£"),
+        "CNY" | "JPY" => Some("¥"),
+        "HKD" => Some("HK$"),
+        _ => None,
+    }
+}
+
+fn currency_decimals(currency: &str) -> usize {
+    match currency {
+        "JPY" => 0,
+        _ => 2,
+    }
+}
+
+pub fn format_amount(value: f64, currency: &str, locale: &str) -> String {
+    let (group_sep, decimal_sep, symbol_after) = locale_rules(locale);
+    let decimals = currency_decimals(currency);
+
+    let formatted = format!("{:.*}", decimals, value.abs());
+    let (int_part, frac_part) = match formatted.split_once('.') {
+        Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+        None => (formatted.as_str(), None),
+    };
+
+    let mut grouped = String::new();
+    for (i, c) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push(group_sep);
+        }
+        grouped.push(c);
+    }
+    if let Some(frac_part) = frac_part {
+        grouped.push(decimal_sep);
+        grouped.push_str(frac_part);
+    }
+
+    let sign = if value < 0.0 && formatted.chars().any(|c| c != '0' && c != '.') { "-" } else { "" };
+    match (currency_symbol(currency), symbol_after) {
+        (Some(symbol), false) => format!("{}{}{}", sign, symbol, grouped),
+        (Some(symbol), true) => format!("{}{} {}", sign, grouped, symbol),
+        (None, _) => format!("{}{} {}", sign, grouped, currency),
+    }
+}
+
+// rates 为每 1 USD 可兑换的目标货币数量，没有对应汇率时回退为 USD 展示
+pub fn display_values(wallet: &WalletConfig, data: &PortfolioData, rates: &HashMap<String, f64>) -> DisplayValues {
+    let locale = wallet.locale.as_deref().unwrap_or(DEFAULT_LOCALE);
+    let mut currency = wallet.display_currency.as_deref().unwrap_or(DEFAULT_CURRENCY).to_uppercase();
+
+    let rate = if currency == DEFAULT_CURRENCY {
+        1.0
+    } else if let Some(rate) = rates.get(&currency) {
+        *rate
+    } else {
+        tracing::warn!("未配置 {} 的汇率，钱包 {} 按 USD 展示", currency, wallet.name);
+        currency = DEFAULT_CURRENCY.to_string();
+        1.0
+    };
+
+    // 展示值只保留两位小数，换算汇率时用浮点数即可
+    let convert = |value: rust_decimal::Decimal| value.to_f64().unwrap_or(0.0) * rate;
+    DisplayValues {
+        portfolio_total: format_amount(convert(data.portfolio_total), &currency, locale),
+        usdc_balance: format_amount(convert(data.usdc_balance), &currency, locale),
+        positions_value: format_amount(convert(data.positions_value), &currency, locale),
+        currency,
+        locale: locale.to_string(),
+    }
+}
+
+// 解析 FX_RATES，格式如 EUR:0.92,CNY:7.1
+pub fn parse_rates(value: Option<&str>) -> HashMap<String, f64> {
+    let mut rates = HashMap::new();
+    for pair in value.unwrap_or("").split(',').filter(|p| !p.trim().is_empty()) {
+        match pair.split_once(':').map(|(c, r)| (c.trim().to_uppercase(), r.trim().parse::<f64>())) {
+            Some((currency, Ok(rate))) if rate > 0.0 => {
+                rates.insert(currency, rate);
+            }
+            _ => tracing::warn!("无法解析汇率配置: {}", pair),
+        }
+    }
+    rates
+}
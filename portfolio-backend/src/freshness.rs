@@ -0,0 +1,54 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::SharedState;
+
+// 整条刷新链路的存活检查：超过 FRESHNESS_SLA_SECS 没有任何钱包刷新成功时发送告警，恢复后发送恢复通知。
+// 持续超时期间按 FRESHNESS_ALERT_COOLDOWN_SECS 重复提醒，避免刷屏
+pub fn spawn(state: SharedState) {
+    let sla_secs = state.settings.freshness_sla_secs;
+    if sla_secs == 0 {
+        return;
+    }
+    if !state.notifier.enabled() {
+        tracing::warn!("已设置 FRESHNESS_SLA_SECS 但未配置 WEBHOOK_URL，超时只会记录日志");
+    }
+
+    tokio::spawn(async move {
+        let sla_ms = sla_secs as i64 * 1000;
+        let cooldown_ms = state.settings.freshness_alert_cooldown_secs as i64 * 1000;
+        let check_every = Duration::from_secs((sla_secs / 4).clamp(1, 60));
+        let mut breached_since: Option<i64> = None;
+        let mut last_alert: Option<i64> = None;
+
+        let mut interval = tokio::time::interval(check_every);
+        loop {
+            interval.tick().await;
+            let now = chrono::Utc::now().timestamp_millis();
+            let last_success = state.last_refresh_success.load(Ordering::Relaxed);
+            let stale_ms = now - last_success;
+
+            if stale_ms > sla_ms {
+                breached_since.get_or_insert(now);
+                if last_alert.is_some_and(|t| now - t < cooldown_ms) {
+                    continue;
+                }
+                last_alert = Some(now);
+                tracing::error!("已有 {} 秒没有任何钱包刷新成功，超过 SLA {} 秒", stale_ms / 1000, sla_secs);
+                state.notifier.send(
+                    "freshness_sla_breached",
+                    &format!("已有 {} 秒没有任何钱包刷新成功", stale_ms / 1000),
+                    serde_json::json!({ "last_success": last_success, "sla_secs": sla_secs }),
+                ).await;
+            } else if let Some(since) = breached_since.take() {
+                last_alert = None;
+                tracing::info!("数据刷新已恢复，中断约 {} 秒", (now - since) / 1000);
+                state.notifier.send(
+                    "freshness_sla_recovered",
+                    "数据刷新已恢复",
+                    serde_json::json!({ "last_success": last_success, "breached_since": since }),
+                ).await;
+            }
+        }
+    });
+}
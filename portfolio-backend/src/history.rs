@@ -0,0 +1,264 @@
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::db::PortfolioSnapshot;
+use crate::portfolio::PortfolioData;
+
+// 早于 2020-01-01 或比当前时间晚一天以上的时间戳视为异常数据
+const MIN_PLAUSIBLE_MS: i64 = 1_577_836_800_000;
+const MAX_FUTURE_MS: i64 = 86_400_000;
+
+// 历史数据的分组粒度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bucket {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    pub fn parse(value: Option<&str>) -> Bucket {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            Some("hour") => Bucket::Hour,
+            Some("day") => Bucket::Day,
+            _ => Bucket::Minute,
+        }
+    }
+
+    pub fn millis(self) -> i64 {
+        match self {
+            Bucket::Minute => 60_000,
+            Bucket::Hour => 3_600_000,
+            Bucket::Day => 86_400_000,
+        }
+    }
+
+    // 将毫秒时间戳向下取整到所在分组的起点
+    pub fn start_of(self, ts: i64) -> i64 {
+        (ts / self.millis()) * self.millis()
+    }
+}
+
+// 同一分组内同一钱包有多条快照时取值的方式：
+// - Last：取组内最后一条，和实时数据一致，但组内的波动被忽略；
+// - Avg：取组内平均值，曲线更平滑，但不再等于任何一个真实时刻的余额
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Last,
+    Avg,
+}
+
+impl Aggregation {
+    pub fn parse(value: Option<&str>) -> Aggregation {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            Some("avg") | Some("mean") => Aggregation::Avg,
+            _ => Aggregation::Last,
+        }
+    }
+}
+
+// 分组内单个钱包的累计值
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BucketValue {
+    sum: Decimal,
+    count: usize,
+    last: Decimal,
+}
+
+impl BucketValue {
+    pub fn add(&mut self, value: Decimal) {
+        self.sum += value;
+        self.count += 1;
+        self.last = value;
+    }
+
+    // 平均值按数据库列的精度保留 6 位小数
+    pub fn value(&self, agg: Aggregation) -> Decimal {
+        match agg {
+            Aggregation::Last => self.last,
+            Aggregation::Avg if self.count > 0 => (self.sum / Decimal::from(self.count)).round_dp(6),
+            Aggregation::Avg => Decimal::ZERO,
+        }
+    }
+}
+
+// 解析逗号分隔的地址列表，空列表视为不过滤
+pub fn parse_address_list(value: Option<&str>) -> Option<Vec<String>> {
+    let list: Vec<String> = value?
+        .split(',')
+        .map(|a| crate::config::canonical_address(a.trim()))
+        .filter(|a| !a.is_empty())
+        .collect();
+    if list.is_empty() {
+        None
+    } else {
+        Some(list)
+    }
+}
+
+pub fn matches_filter(filter: Option<&[String]>, address: &str) -> bool {
+    match filter {
+        Some(list) => list.iter().any(|a| a.eq_ignore_ascii_case(address)),
+        None => true,
+    }
+}
+
+// 历史数据中出现异常时间戳时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidTimestamp {
+    // 丢弃该行
+    Skip,
+    // 截断到合理范围内
+    Clamp,
+}
+
+impl InvalidTimestamp {
+    pub fn parse(value: Option<&str>) -> InvalidTimestamp {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            Some("clamp") => InvalidTimestamp::Clamp,
+            _ => InvalidTimestamp::Skip,
+        }
+    }
+}
+
+// 校验快照时间戳，异常时按策略丢弃或截断并记录警告，避免生成错误的分组
+pub fn sanitize_snapshot(mut snapshot: PortfolioSnapshot, policy: InvalidTimestamp) -> Option<PortfolioSnapshot> {
+    let ts = snapshot.timestamp.timestamp_millis();
+    let max = chrono::Utc::now().timestamp_millis() + MAX_FUTURE_MS;
+    if (MIN_PLAUSIBLE_MS..=max).contains(&ts) {
+        return Some(snapshot);
+    }
+
+    match policy {
+        InvalidTimestamp::Skip => {
+            tracing::warn!("跳过时间戳异常的快照: id={}, 钱包 {}, 时间戳 {}", snapshot.id, snapshot.proxy_address, ts);
+            None
+        }
+        InvalidTimestamp::Clamp => {
+            let clamped = ts.clamp(MIN_PLAUSIBLE_MS, max);
+            tracing::warn!("快照时间戳异常，已截断: id={}, 钱包 {}, {} -> {}", snapshot.id, snapshot.proxy_address, ts, clamped);
+            snapshot.timestamp = chrono::DateTime::from_timestamp_millis(clamped)?;
+            Some(snapshot)
+        }
+    }
+}
+
+// backfilled 表示该分组中包含补齐的快照；金额和其他接口一样输出为数字字符串
+pub fn history_entry(timestamp: i64, wallets: &HashMap<String, Decimal>, backfilled: bool) -> serde_json::Value {
+    let total: Decimal = wallets.values().sum();
+    serde_json::json!({
+        "timestamp": timestamp,
+        "total": total,
+        "wallets": wallets,
+        "backfilled": backfilled
+    })
+}
+
+// 按 agg 取出分组内各钱包的值后生成历史条目，/api/portfolio/history 和 /api/dashboard 共用，两者的结果一致
+pub fn bucket_entry(timestamp: i64, wallets: HashMap<String, BucketValue>, backfilled: bool, agg: Aggregation) -> serde_json::Value {
+    let values = wallets.into_iter().map(|(address, value)| (address, value.value(agg))).collect();
+    history_entry(timestamp, &values, backfilled)
+}
+
+// 最近一段时间已写入数据库的快照，按时间顺序保存所有钱包的数据，用于短窗口历史查询时绕过数据库。
+// 数据库是权威来源，缓冲区只是它最近一段的副本：调用方只在快照真正写入后 push
+pub struct HistoryBuffer {
+    retention_ms: i64,
+    // (第一次写入的时间, 数据)，用来判断缓冲区是否完整覆盖请求的窗口
+    inner: Mutex<(Option<i64>, VecDeque<PortfolioData>)>,
+}
+
+impl HistoryBuffer {
+    pub fn new(retention_minutes: i64) -> Self {
+        Self {
+            retention_ms: retention_minutes.max(0) * 60_000,
+            inner: Mutex::new((None, VecDeque::new())),
+        }
+    }
+
+    pub fn push(&self, items: &[PortfolioData]) {
+        if self.retention_ms == 0 || items.is_empty() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut inner = self.inner.lock().unwrap();
+        inner.0.get_or_insert(now);
+        inner.1.extend(items.iter().cloned());
+        // 重试队列补写的快照比已有的数据早，重新按时间排序
+        if !inner.1.iter().zip(inner.1.iter().skip(1)).all(|(a, b)| a.last_updated <= b.last_updated) {
+            inner.1.make_contiguous().sort_by_key(|d| d.last_updated);
+        }
+        while inner.1.front().is_some_and(|d| d.last_updated < now - self.retention_ms) {
+            inner.1.pop_front();
+        }
+    }
+
+    // 窗口起点在保留范围内且服务运行期间一直在写入时，才返回数据，否则返回 None 由调用方查询数据库
+    pub fn window(&self, from_ms: i64) -> Option<Vec<PortfolioData>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        if self.retention_ms == 0 || from_ms < now - self.retention_ms {
+            return None;
+        }
+        let inner = self.inner.lock().unwrap();
+        if !matches!(inner.0, Some(started) if started <= from_ms) {
+            return None;
+        }
+        Some(inner.1.iter().filter(|d| d.last_updated >= from_ms).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_at(ts: i64) -> PortfolioSnapshot {
+        PortfolioSnapshot::from_portfolio_data(&PortfolioData { last_updated: ts, ..Default::default() })
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_skipped_or_clamped() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let far_future = now + 10 * 365 * 86_400_000;
+
+        assert!(sanitize_snapshot(snapshot_at(0), InvalidTimestamp::Skip).is_none());
+        assert!(sanitize_snapshot(snapshot_at(far_future), InvalidTimestamp::Skip).is_none());
+
+        let clamped = sanitize_snapshot(snapshot_at(0), InvalidTimestamp::Clamp).unwrap();
+        assert_eq!(clamped.timestamp.timestamp_millis(), MIN_PLAUSIBLE_MS);
+        let clamped = sanitize_snapshot(snapshot_at(far_future), InvalidTimestamp::Clamp).unwrap();
+        assert!(clamped.timestamp.timestamp_millis() <= chrono::Utc::now().timestamp_millis() + MAX_FUTURE_MS);
+
+        // 正常的时间戳原样保留
+        let valid = sanitize_snapshot(snapshot_at(now), InvalidTimestamp::Skip).unwrap();
+        assert_eq!(valid.timestamp.timestamp_millis(), now);
+    }
+
+    #[test]
+    fn bucket_entries_apply_the_aggregation() {
+        let mut value = BucketValue::default();
+        for amount in [10, 20, 60] {
+            value.add(Decimal::from(amount));
+        }
+        let wallets = HashMap::from([("0xa".to_string(), value)]);
+
+        let last = bucket_entry(60_000, wallets.clone(), false, Aggregation::Last);
+        assert_eq!(last["wallets"]["0xa"], "60");
+        let avg = bucket_entry(60_000, wallets, true, Aggregation::Avg);
+        assert_eq!(avg["wallets"]["0xa"], "30");
+        assert_eq!(avg["total"], "30");
+        assert_eq!(avg["backfilled"], true);
+    }
+
+    #[test]
+    fn empty_window_is_an_empty_list_not_a_miss() {
+        let buffer = HistoryBuffer::new(60);
+        let now = chrono::Utc::now().timestamp_millis();
+        buffer.push(&[PortfolioData { last_updated: now - 1_000, ..Default::default() }]);
+
+        // 缓冲区覆盖了窗口但窗口内没有快照：返回 Some([])，接口据此返回 200 []，不再查询数据库
+        assert_eq!(buffer.window(now + 1_000).map(|items| items.len()), Some(0));
+        // 窗口早于缓冲区开始写入的时间，交给数据库查询
+        assert!(buffer.window(now - 10 * 60_000).is_none());
+    }
+}
@@ -0,0 +1,97 @@
+use alloy::primitives::{keccak256, Address, B256};
+use alloy::providers::ProviderBuilder;
+use alloy::sol;
+use std::str::FromStr;
+
+use crate::config::WalletConfig;
+use crate::error::AppError;
+use crate::AppState;
+
+// 以太坊主网 ENS Registry
+const ENS_REGISTRY_ADDRESS: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+
+sol! {
+    #[sol(rpc)]
+    interface IEnsRegistry {
+        function resolver(bytes32 node) external view returns (address);
+    }
+
+    #[sol(rpc)]
+    interface IEnsReverseResolver {
+        function name(bytes32 node) external view returns (string);
+    }
+}
+
+fn namehash(name: &str) -> B256 {
+    let mut node = B256::ZERO;
+    for label in name.rsplit('.') {
+        let label_hash = keccak256(label.as_bytes());
+        let mut buf = [0u8; 64];
+        buf[..32].copy_from_slice(node.as_slice());
+        buf[32..].copy_from_slice(label_hash.as_slice());
+        node = keccak256(buf);
+    }
+    node
+}
+
+// 反向解析 <addr>.addr.reverse，没有设置主名称时返回 None
+async fn reverse_lookup(rpc_url: &str, address: &str) -> Result<Option<String>, AppError> {
+    let addr = Address::from_str(address)
+        .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+    let url = rpc_url.parse()
+        .map_err(|e| AppError::RpcConnectError(format!("无效的 ENS RPC 地址: {}", e)))?;
+    let provider = ProviderBuilder::new().connect_http(url);
+
+    let node = namehash(&format!("{}.addr.reverse", hex_lower(&addr)));
+    let registry = IEnsRegistry::new(ENS_REGISTRY_ADDRESS.parse().unwrap(), &provider);
+    let resolver = registry.resolver(node).call().await.map_err(AppError::from)?;
+    if resolver.is_zero() {
+        return Ok(None);
+    }
+
+    let name = IEnsReverseResolver::new(resolver, &provider)
+        .name(node)
+        .call()
+        .await
+        .map_err(AppError::from)?;
+    Ok(Some(name).filter(|n| !n.is_empty()))
+}
+
+// 启动检查用：对零地址做一次反向解析，确认 ENS RPC 可用
+pub async fn check_ens(rpc_url: &str) -> Result<(), AppError> {
+    reverse_lookup(rpc_url, &Address::ZERO.to_string()).await.map(|_| ())
+}
+
+fn hex_lower(address: &Address) -> String {
+    format!("{:x}", address)
+}
+
+// 为钱包补充 label：优先使用配置的标签，开启 ENS_LOOKUP 时再尝试 ENS 反向解析。
+// ENS 结果（包括没有名称）会缓存，解析失败时不写缓存也不影响响应
+pub async fn apply_labels(state: &AppState, wallets: &mut [WalletConfig]) {
+    for wallet in wallets.iter_mut() {
+        if wallet.label.is_none() {
+            wallet.label = state.settings.wallet_labels.get(&wallet.proxy_address.to_lowercase()).cloned();
+        }
+    }
+
+    let Some(rpc_url) = state.settings.ens_rpc_url.as_deref().filter(|_| state.settings.ens_lookup) else {
+        return;
+    };
+
+    for wallet in wallets.iter_mut().filter(|w| w.label.is_none() && !w.paper) {
+        let key = wallet.proxy_address.to_lowercase();
+        if let Some(cached) = state.ens_cache.read().await.get(&key) {
+            wallet.label = cached.clone();
+            continue;
+        }
+
+        match reverse_lookup(rpc_url, &wallet.proxy_address).await {
+            Ok(name) => {
+                wallet.label = name.clone();
+                state.ens_cache.write().await.insert(key, name);
+            }
+            Err(e) => tracing::warn!("ENS 反向解析 {} 失败: {}", wallet.proxy_address, e),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use futures::{Stream, StreamExt};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::portfolio::PortfolioData;
+use crate::SharedState;
+
+// 推送给实时订阅者的一批钱包数据
+pub type Update = Arc<Vec<PortfolioData>>;
+
+// 刷新结果先进入合并任务，再广播给所有订阅者；窗口内的多次更新合并成一次，同一钱包以最后一次为准
+pub struct Broadcaster {
+    input: mpsc::UnboundedSender<Vec<PortfolioData>>,
+    output: broadcast::Sender<Update>,
+}
+
+impl Broadcaster {
+    pub fn new(window_ms: u64) -> Self {
+        let (input, rx) = mpsc::unbounded_channel();
+        let (output, _) = broadcast::channel(16);
+        tokio::spawn(feed(rx, output.clone(), Duration::from_millis(window_ms)));
+        Self { input, output }
+    }
+
+    pub fn publish(&self, items: &[PortfolioData]) {
+        if items.is_empty() {
+            return;
+        }
+        let _ = self.input.send(items.to_vec());
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Update> {
+        self.output.subscribe()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.output.receiver_count()
+    }
+}
+
+async fn feed(
+    mut rx: mpsc::UnboundedReceiver<Vec<PortfolioData>>,
+    output: broadcast::Sender<Update>,
+    window: Duration,
+) {
+    while let Some(first) = rx.recv().await {
+        let mut pending: BTreeMap<String, PortfolioData> = BTreeMap::new();
+        merge(&mut pending, first);
+
+        if !window.is_zero() {
+            let deadline = tokio::time::Instant::now() + window;
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    next = rx.recv() => match next {
+                        Some(items) => merge(&mut pending, items),
+                        None => break,
+                    },
+                }
+            }
+        }
+
+        // 没有订阅者时发送失败，直接丢弃
+        let _ = output.send(Arc::new(pending.into_values().collect()));
+    }
+}
+
+fn merge(pending: &mut BTreeMap<String, PortfolioData>, items: Vec<PortfolioData>) {
+    for data in items {
+        pending.insert(data.proxy_address.clone(), data);
+    }
+}
+
+// 连接后先发送一次当前缓存概览（type=snapshot），之后每次刷新完成推送更新的钱包（type=update）
+#[utoipa::path(get, path = "/api/portfolio/ws", responses(
+    (status = 101, description = "升级为 WebSocket，消息为 JSON 文本：先 type=snapshot，之后每次刷新完成推送 type=update"),
+))]
+pub async fn ws(ws: WebSocketUpgrade, State(state): State<SharedState>) -> Response {
+    ws.on_upgrade(move |socket| serve_socket(socket, state))
+}
+
+async fn serve_socket(mut socket: WebSocket, state: SharedState) {
+    // 先订阅再读取缓存，避免两者之间完成的刷新被漏掉
+    let mut updates = state.live.subscribe();
+
+    // 读取初始数据失败时发送 type=error，连接保持，后续更新照常推送
+    let snapshot = match crate::cached_overview(&state, None, false).await {
+        Ok(overview) => {
+            let mut snapshot = serde_json::json!(overview);
+            snapshot["type"] = serde_json::json!("snapshot");
+            snapshot
+        }
+        Err(e) => serde_json::json!({ "type": "error", "error": e.to_string() }),
+    };
+    if socket.send(Message::Text(snapshot.to_string().into())).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                // 客户端只会发送 ping/pong，关闭或出错时结束
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            update = updates.recv() => match update {
+                Ok(items) => {
+                    let wallets = crate::with_display(&state, items.to_vec()).await;
+                    let message = serde_json::json!({ "type": "update", "wallets": wallets });
+                    if socket.send(Message::Text(message.to_string().into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("WebSocket 客户端处理过慢，跳过 {} 次更新", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    tracing::debug!("WebSocket 客户端已断开");
+}
+
+// SSE 版本的实时推送：连接后先发送一次当前缓存概览（event: snapshot），之后每次刷新完成推送包含汇总的完整概览（event: update）
+#[utoipa::path(get, path = "/api/portfolio/stream", responses(
+    (status = 200, content_type = "text/event-stream", description = "先发送 event: snapshot，之后每次刷新完成发送 event: update，data 为 JSON"),
+))]
+pub async fn sse(State(state): State<SharedState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // 同 WebSocket，先订阅再读取缓存
+    let updates = state.live.subscribe();
+    let snapshot = overview_event(&state, "snapshot").await;
+
+    let rest = futures::stream::unfold((state, updates), |(state, mut updates)| async move {
+        loop {
+            match updates.recv().await {
+                Ok(_) => {
+                    let event = overview_event(&state, "update").await;
+                    return Some((Ok(event), (state, updates)));
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("SSE 客户端处理过慢，跳过 {} 次更新", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    // 定期发送注释行，避免空闲连接被代理断开
+    Sse::new(futures::stream::once(async move { Ok(snapshot) }).chain(rest))
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+}
+
+// 读取缓存概览失败时发送 event: error，连接保持
+async fn overview_event(state: &SharedState, kind: &str) -> Event {
+    match crate::cached_overview(state, None, false).await {
+        Ok(overview) => Event::default().event(kind).data(serde_json::json!(overview).to_string()),
+        Err(e) => Event::default().event("error").data(serde_json::json!({ "error": e.to_string() }).to_string()),
+    }
+}
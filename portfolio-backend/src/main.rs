@@ -1,45 +1,212 @@
+mod access_log;
+mod admin;
+mod analytics;
+mod audit;
+mod auth;
+mod backfill;
+mod check;
 mod config;
+mod consistency;
 mod db;
+mod encoding;
 mod error;
+mod error_log;
+mod format;
+mod freshness;
+mod history;
+mod labels;
+mod live;
+mod notify;
+mod openapi;
 mod portfolio;
+mod publish;
+mod response;
+mod retention;
+mod retry;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
 
-use axum::{Router, routing::get, Json, extract::Query};
+use axum::{Router, routing::{delete, get, post, put}, Json, extract::{Path, Query}};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tower_http::cors::{CorsLayer, Any};
+use tower_http::cors::{AllowOrigin, CorsLayer, Any};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use sqlx::mysql::MySqlPool;
 
-use crate::config::WalletConfig;
+use crate::config::{Settings, WalletConfig};
+use crate::error::AppError;
+use crate::history::{Bucket, bucket_entry};
 use crate::portfolio::{PortfolioData, PortfolioService};
 
 type SharedState = Arc<AppState>;
 
 struct AppState {
-    wallets: Vec<WalletConfig>,
+    settings: Settings,
+    metrics: metrics_exporter_prometheus::PrometheusHandle,
+    portfolio: PortfolioService,
+    wallets: RwLock<Vec<WalletConfig>>,
     cache: RwLock<std::collections::HashMap<String, PortfolioData>>,
     db_pool: MySqlPool,
+    refresh_db_permits: tokio::sync::Semaphore,
+    // ENS 反向解析结果缓存，key 为小写地址
+    ens_cache: RwLock<std::collections::HashMap<String, Option<String>>>,
+    recent_history: history::HistoryBuffer,
+    // 最近一次缓存与数据库一致性检查的结果
+    consistency: RwLock<Option<consistency::CheckReport>>,
+    live: live::Broadcaster,
+    save_retries: retry::SaveRetryQueue,
+    errors: error_log::ErrorLog,
+    // 运行时暂停刷新的钱包 wallet_id，重启后恢复
+    paused: RwLock<std::collections::HashSet<String>>,
+    // 每个钱包最近一次链上 CTF 核对的结果，key 为代理地址
+    ctf_reports: RwLock<std::collections::HashMap<String, portfolio::CtfReport>>,
+    config_history: audit::ConfigHistory,
+    // 启动预热（缓存填充和第一次刷新）是否已完成
+    warmed_up: std::sync::atomic::AtomicBool,
+    // 最近一次有钱包刷新成功的时间（毫秒），初始为启动时间
+    last_refresh_success: std::sync::atomic::AtomicI64,
+    notifier: notify::Notifier,
+    publisher: publish::Publisher,
+    // 缓存过期时间，None 表示不判断过期
+    cache_ttl: Option<chrono::Duration>,
+    // 全部缓存过期时触发的后台刷新是否正在进行，避免每次请求都再触发一次
+    stale_refresh_running: std::sync::atomic::AtomicBool,
+    // 最近一次 /api/portfolio/refresh 的结果；刷新期间一直持有锁，同时到达的请求等待后共享这次结果
+    last_refresh: tokio::sync::Mutex<Option<LastRefresh>>,
+    // 各地址最近一次单钱包刷新的开始时间，和全量刷新共用 REFRESH_COOLDOWN_SECS
+    wallet_refreshes: std::sync::Mutex<std::collections::HashMap<String, std::time::Instant>>,
 }
 
-#[derive(serde::Deserialize)]
+struct LastRefresh {
+    finished: std::time::Instant,
+    components: portfolio::Components,
+    // 刷新失败时为 None，只用于冷却判断
+    results: Option<Vec<PortfolioData>>,
+}
+
+impl AppState {
+    // 当前钱包列表的快照，避免在 await 期间持有读锁
+    async fn wallets(&self) -> Vec<WalletConfig> {
+        self.wallets.read().await.clone()
+    }
+
+    // 缓存数据的年龄（秒）以及是否已超过 CACHE_TTL_SECS
+    fn staleness(&self, data: &PortfolioData, now: i64) -> (i64, bool) {
+        let age = (now - data.last_updated).max(0) / 1000;
+        let stale = self.cache_ttl.is_some_and(|ttl| age >= ttl.num_seconds());
+        (age, stale)
+    }
+
+    // 总价值低于 MIN_PORTFOLIO_VALUE 的零头钱包，未配置时没有
+    fn is_dust(&self, portfolio_total: rust_decimal::Decimal) -> bool {
+        portfolio_total < self.settings.min_portfolio_value
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ApprovalsQuery {
+    wallet: String,
+}
+
+// 单个钱包持仓明细的选项，依次应用 min_value 过滤、sort 排序和 limit 截断
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PositionsQuery {
+    sort: Option<String>,
+    limit: Option<usize>,
+    min_value: Option<rust_decimal::Decimal>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RefreshQuery {
+    // 逗号分隔的 usdc/positions，只刷新选中的部分，默认全部
+    components: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 struct HistoryQuery {
     hours: Option<i64>,
+    // 增量同步游标（毫秒时间戳），同时给出时优先于 hours
+    since: Option<i64>,
+    // 不做分组，逐条返回原始快照，适合放大查看很短的时间窗口
+    #[serde(default)]
+    raw: bool,
+    // 按快照行分页，默认 HISTORY_PAGE_SIZE 行，最多 HISTORY_MAX_PAGE_SIZE 行；总行数在 X-Total-Count 中返回
+    limit: Option<i64>,
+    offset: Option<i64>,
+    // 分组粒度 minute/hour/day，默认 minute；粒度越粗点数越少，但组内的变化会被合并
+    bucket: Option<String>,
+    // 组内取值方式 last/avg，默认 last
+    agg: Option<String>,
+    // 逗号分隔的代理地址，只返回这些钱包的历史；没有匹配的钱包时返回空数组
+    addresses: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CachedQuery {
+    // 从钱包列表中去掉总价值低于 MIN_PORTFOLIO_VALUE 的钱包，合计值不受影响
+    #[serde(default)]
+    hide_dust: bool,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AlertsQuery {
+    // 默认 24 小时
+    hours: Option<i64>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryCsvQuery {
+    hours: Option<i64>,
+}
+
+// 组合接口的过滤条件：
+// - hours：历史数据的时间窗口（默认24小时）
+// - bucket：历史分组粒度 minute/hour/day（默认 minute）
+// - agg：组内取值方式 last/avg（默认 last），与 /api/portfolio/history 相同
+// - wallets：逗号分隔的代理地址，同时作用于钱包配置、缓存和历史三部分，历史在数据库查询中过滤
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DashboardQuery {
+    hours: Option<i64>,
+    bucket: Option<String>,
+    agg: Option<String>,
+    wallets: Option<String>,
 }
 
 #[tokio::main]
 async fn main() {
+    dotenvy::from_path("../.env").ok();
+
+    // LOG_FORMAT=json 时输出结构化 JSON 日志
+    let json_logs = std::env::var("LOG_FORMAT").is_ok_and(|v| v.eq_ignore_ascii_case("json"));
     tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new("info"))
-        .with(tracing_subscriber::fmt::layer())
+        // 默认 info；数据库查询耗时等 debug 日志可通过 RUST_LOG=portfolio_backend=debug 打开
+        .with(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")))
+        .with(json_logs.then(|| tracing_subscriber::fmt::layer().json()))
+        .with((!json_logs).then(tracing_subscriber::fmt::layer))
         .init();
+    // 要在任何指标被记录之前安装，包括下面的数据库查询
+    let metrics = telemetry::install();
 
-    dotenvy::from_path("../.env").ok();
-    
-    let wallets = config::load_wallets_from_env();
-    tracing::info!("加载了 {} 个钱包配置", wallets.len());
+    let settings = Settings::from_env();
+    tracing::info!("当前链: {}", settings.chain.name());
+
+    // --check 或 CHECK_ONLY=1：逐项检查配置和外部依赖后退出，不启动 HTTP 服务
+    if std::env::args().any(|arg| arg == "--check") || config::env_flag("CHECK_ONLY") {
+        let passed = check::run(&settings).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
 
     // 连接数据库
-    let db_pool = match db::create_pool().await {
+    let db_pool = match db::create_pool(&settings).await {
         Ok(pool) => {
             tracing::info!("数据库连接成功");
             pool
@@ -50,183 +217,1417 @@ async fn main() {
         }
     };
 
+    if config::env_flag_or("RUN_MIGRATIONS", true) {
+        if let Err(e) = db::run_migrations(&db_pool).await {
+            tracing::error!("{}", e);
+            panic!("数据库迁移失败");
+        }
+    }
+
+    let wallets = match load_wallets(&settings, Some(&db_pool)).await {
+        Ok(wallets) => wallets,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("加载了 {} 个钱包配置", wallets.len());
+    // 首次以数据库为钱包来源运行时，wallets 表为空，把环境变量或配置文件里的钱包写入作为初始数据
+    if settings.wallets_from_db && !wallets.is_empty() {
+        match db::seed_wallets(&db_pool, &wallets).await {
+            Ok(0) => {}
+            Ok(seeded) => tracing::info!(seeded, "wallets 表为空，已写入初始钱包配置"),
+            Err(e) => tracing::error!("写入初始钱包配置失败: {}", e),
+        }
+    }
+    if wallets.is_empty() {
+        tracing::warn!("未加载到任何钱包配置！请设置 WALLET_1_PROXY_ADDRESS ... WALLET_10_PROXY_ADDRESS 环境变量");
+        if config::env_flag("REQUIRE_WALLETS") {
+            tracing::error!("REQUIRE_WALLETS 已开启且没有钱包配置，拒绝启动");
+            std::process::exit(1);
+        }
+    }
+
+    // PortfolioService 只创建一次，所有请求共用同一个 HTTP 客户端和 RPC provider
+    let portfolio = build_portfolio_service(&settings);
+
     let state = Arc::new(AppState {
-        wallets,
+        metrics,
+        portfolio,
+        wallets: RwLock::new(wallets),
         cache: RwLock::new(std::collections::HashMap::new()),
         db_pool,
+        refresh_db_permits: tokio::sync::Semaphore::new(settings.refresh_db_concurrency),
+        ens_cache: RwLock::new(std::collections::HashMap::new()),
+        recent_history: history::HistoryBuffer::new(settings.history_buffer_minutes),
+        consistency: RwLock::new(None),
+        live: live::Broadcaster::new(settings.live_batch_window_ms),
+        save_retries: retry::SaveRetryQueue::new(
+            settings.save_retry_queue_size,
+            settings.save_retry_max,
+            settings.save_retry_base_ms,
+        ),
+        errors: error_log::ErrorLog::new(settings.error_log_size),
+        paused: RwLock::new(std::collections::HashSet::new()),
+        ctf_reports: RwLock::new(std::collections::HashMap::new()),
+        config_history: audit::ConfigHistory::new(settings.config_history_size),
+        warmed_up: std::sync::atomic::AtomicBool::new(false),
+        last_refresh_success: std::sync::atomic::AtomicI64::new(chrono::Utc::now().timestamp_millis()),
+        notifier: notify::Notifier::new(&settings),
+        publisher: publish::Publisher::connect(&settings).await,
+        cache_ttl: (settings.cache_ttl_secs > 0).then(|| chrono::Duration::seconds(settings.cache_ttl_secs as i64)),
+        stale_refresh_running: std::sync::atomic::AtomicBool::new(false),
+        last_refresh: tokio::sync::Mutex::new(None),
+        wallet_refreshes: std::sync::Mutex::new(std::collections::HashMap::new()),
+        settings,
     });
 
+    consistency::spawn(state.clone());
+    retry::spawn(state.clone());
+    freshness::spawn(state.clone());
+    retention::spawn(state.clone());
+    tokio::spawn(warmup(state.clone()));
+    if state.settings.refresh_interval_secs > 0 {
+        tokio::spawn(background_refresh(state.clone()));
+    }
+
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(cors_origin(&state.settings))
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([
+            axum::http::HeaderName::from_static("x-next-cursor"),
+            axum::http::HeaderName::from_static("x-request-id"),
+            axum::http::HeaderName::from_static("x-truncated"),
+            axum::http::HeaderName::from_static("x-total-count"),
+        ]);
 
-    let app = Router::new()
-        .route("/api/health", get(health))
+    let mut app = Router::new()
+        .route("/metrics", get(telemetry::metrics))
         .route("/api/wallets", get(get_wallets))
         .route("/api/portfolio/refresh", get(refresh_portfolio))
+        .route("/api/portfolio/refresh/{address}", post(refresh_wallet))
         .route("/api/portfolio/cached", get(get_cached))
+        .route("/api/portfolio/wallet/{address}", get(get_wallet_portfolio))
         .route("/api/portfolio/history", get(get_history))
+        .route("/api/portfolio/history.csv", get(get_history_csv))
+        .route("/api/portfolio/ws", get(live::ws))
+        .route("/api/portfolio/stream", get(live::sse))
+        .route("/api/portfolio/twr", get(analytics::twr))
+        .route("/api/portfolio/beta", get(analytics::beta))
+        .route("/api/portfolio/drawdown", get(analytics::drawdown))
+        .route("/api/portfolio/summary", get(analytics::summary))
+        .route("/api/portfolio/timeseries", get(analytics::timeseries))
+        .route("/api/portfolio/compare", get(analytics::compare))
+        .route("/api/portfolio/alerts", get(get_alerts))
+        .route("/api/dashboard", get(get_dashboard))
+        .route("/api/portfolio/positions/{address}", get(get_positions))
+        .route("/api/positions/by-market/{condition_id}", get(get_positions_by_market));
+
+    if state.settings.approvals_check {
+        app = app
+            .route("/api/portfolio/approvals", get(get_approvals))
+            .route("/api/portfolio/approvals/gas", get(get_approval_gas));
+    }
+
+    // 健康检查和就绪检查始终公开，其余读接口在配置了 API_KEY 时需要鉴权
+    let app = Router::new()
+        .route("/api/health", get(health))
+        .route("/api/health/ready", get(health_ready))
+        .route("/api/openapi.json", get(openapi::spec))
+        .route("/api/docs", get(openapi::docs))
+        .merge(app.route_layer(axum::middleware::from_fn_with_state(state.clone(), auth::require_api_key)));
+
+    let admin = Router::new()
+        .route("/api/admin/backfill", post(backfill::backfill))
+        .route("/api/selftest", get(admin::selftest))
+        .route("/api/admin/diagnostics", get(admin::diagnostics))
+        .route("/api/admin/errors", get(admin::recent_errors))
+        .route("/api/admin/config-history", get(admin::config_history))
+        .route("/api/wallets", post(admin::upsert_wallet))
+        .route("/api/wallets/{id}", delete(admin::delete_wallet))
+        .route("/api/admin/wallets", post(admin::upsert_wallet))
+        .route("/api/admin/wallets/{id}", delete(admin::delete_wallet))
+        .route("/api/admin/wallets/{id}/paper-balance", put(admin::set_paper_balance))
+        .route("/api/admin/wallets/{id}/pause", post(admin::pause_wallet))
+        .route("/api/admin/wallets/{id}/resume", post(admin::resume_wallet))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth::require_admin));
+    let mut app = app.merge(admin);
+
+    // 单文件部署时由后端直接提供前端页面；未匹配的 /api 路径仍返回 404，其余路径回退到 index.html 交给前端路由
+    if let Some(dir) = state.settings.frontend_dir.as_deref() {
+        let index = std::path::Path::new(dir).join("index.html");
+        if !index.is_file() {
+            tracing::warn!("FRONTEND_DIR 下没有 index.html: {}", dir);
+        }
+        tracing::info!("提供前端静态文件: {}", dir);
+        app = app
+            .route("/api/{*rest}", axum::routing::any(|| async { axum::http::StatusCode::NOT_FOUND }))
+            .fallback_service(
+                tower_http::services::ServeDir::new(dir)
+                    .fallback(tower_http::services::ServeFile::new(index)),
+            );
+    }
+
+    let db_pool = state.db_pool.clone();
+    let app = app
         .layer(cors)
-        .with_state(state);
+        .layer(axum::middleware::from_fn_with_state(state.clone(), access_log::access_log))
+        .with_state(state.clone());
 
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8405".to_string());
-    let addr = format!("0.0.0.0:{}", port);
-    tracing::info!("后端服务启动在 http://{}", addr);
-    
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let (bind_addr, port) = (state.settings.bind_addr.as_str(), state.settings.port);
+    let listener = match tokio::net::TcpListener::bind((bind_addr, port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("无法监听 {}:{}: {}", bind_addr, port, e);
+            std::process::exit(1);
+        }
+    };
+    match listener.local_addr() {
+        Ok(addr) => tracing::info!("后端服务启动在 http://{}", addr),
+        Err(_) => tracing::info!("后端服务启动在 http://{}:{}", bind_addr, port),
+    }
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    // 所有请求处理完后再关闭连接池，后台任务随进程退出
+    db_pool.close().await;
+    tracing::info!("服务已停止，数据库连接池已关闭");
+}
+
+// 配置了 ALLOWED_ORIGINS 时只允许列表中的来源，否则允许任意来源
+fn cors_origin(settings: &Settings) -> AllowOrigin {
+    let Some(origins) = settings.allowed_origins.as_ref() else {
+        tracing::warn!("未配置 ALLOWED_ORIGINS，CORS 允许任意来源");
+        return AllowOrigin::any();
+    };
+    if origins.is_empty() {
+        tracing::error!("ALLOWED_ORIGINS 中没有有效的来源，所有跨域请求都会被拒绝");
+    } else {
+        tracing::info!("CORS 只允许以下来源: {}", origins.join(", "));
+    }
+    AllowOrigin::list(origins.iter().filter_map(|origin| axum::http::HeaderValue::from_str(origin).ok()))
 }
 
-async fn health() -> &'static str {
-    "OK"
+// 钱包配置的来源依次为：WALLETS_SOURCE=db 时的 wallets 表、CONFIG_FILE 指定的文件、WALLET_{i}_* 环境变量
+// 检查模式下数据库可能连不上，此时 db_pool 为 None，按读取失败处理
+async fn load_wallets(settings: &Settings, db_pool: Option<&MySqlPool>) -> Result<Vec<WalletConfig>, AppError> {
+    let mut wallets = Vec::new();
+    if settings.wallets_from_db {
+        let loaded = match db_pool {
+            Some(pool) => db::load_wallets(pool).await,
+            None => Err(AppError::DbError("数据库不可用".to_string())),
+        };
+        match loaded {
+            Ok(loaded) => {
+                wallets = loaded.into_iter()
+                    .filter(|w| match w.validate() {
+                        Ok(()) => true,
+                        Err(e) => {
+                            tracing::warn!("跳过数据库中配置无效的钱包: {}", e);
+                            false
+                        }
+                    })
+                    .collect();
+            }
+            Err(e) => tracing::error!("从数据库加载钱包失败: {}", e),
+        }
+        if wallets.is_empty() {
+            tracing::warn!("wallets 表为空，回退到环境变量配置");
+        }
+    }
+    if wallets.is_empty() {
+        wallets = match config::env_string("CONFIG_FILE") {
+            Some(path) => {
+                let loaded = config::load_wallets_from_file(std::path::Path::new(&path))?;
+                tracing::info!("从配置文件 {} 加载钱包", path);
+                loaded
+            }
+            None => config::load_wallets_from_env()?,
+        };
+    }
+    Ok(wallets)
 }
 
-async fn get_wallets(
-    axum::extract::State(state): axum::extract::State<SharedState>,
-) -> Json<Vec<WalletConfig>> {
-    Json(state.wallets.clone())
+fn build_portfolio_service(settings: &Settings) -> PortfolioService {
+    let http = portfolio::HttpConfig {
+        timeout: std::time::Duration::from_secs(settings.http_timeout_secs),
+        connect_timeout: std::time::Duration::from_secs(settings.http_connect_timeout_secs),
+        pool_idle_timeout: std::time::Duration::from_secs(settings.http_pool_idle_secs),
+        pool_max_idle_per_host: settings.http_pool_max_idle_per_host,
+    };
+    PortfolioService::with_config(&http)
+        .with_chain(settings.chain)
+        .with_rpc_urls(settings.polygon_rpc_urls.clone())
+        .with_data_api_url(settings.data_api_url.clone())
+        .with_depth_valuation(settings.depth_valuation)
+        .with_value_fields(settings.positions_value_fields.clone())
+        .with_excluded_markets(settings.excluded_markets.clone())
+        .with_positions_count(settings.positions_count)
+        .with_positions_detail(settings.positions_detail)
+        .with_non_json_as_zero(settings.non_json_as_zero)
+        .with_positions_value_ttl(std::time::Duration::from_secs(settings.positions_value_cache_secs))
+        .with_extra_tokens(settings.extra_tokens.clone())
+        .with_retry(settings.fetch_retry_attempts, settings.fetch_retry_base_ms)
 }
 
-async fn refresh_portfolio(
-    axum::extract::State(state): axum::extract::State<SharedState>,
-) -> Json<serde_json::Value> {
-    let service = PortfolioService::new();
-    let mut results = Vec::new();
-    let mut wallet_totals = std::collections::HashMap::new();
-
-    for wallet in &state.wallets {
-        match service.fetch_portfolio(&wallet.proxy_address).await {
-            Ok(data) => {
-                // 保存到数据库
-                if let Err(e) = db::save_snapshot(
-                    &state.db_pool,
-                    &data.proxy_address,
-                    data.portfolio_total,
-                    data.usdc_balance,
-                    data.positions_value,
-                ).await {
-                    tracing::error!("保存快照失败: {}", e);
-                }
-                
-                wallet_totals.insert(wallet.proxy_address.clone(), data.usdc_balance);
-                results.push(data);
+// 收到 Ctrl-C 或 SIGTERM 时返回，之后不再接受新连接，等待进行中的请求完成
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("监听 Ctrl-C 失败: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
             }
             Err(e) => {
-                tracing::error!("获取钱包 {} 数据失败: {}", wallet.name, e);
+                tracing::error!("监听 SIGTERM 失败: {}", e);
+                std::future::pending::<()>().await;
             }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+    tracing::info!("收到退出信号，开始优雅关闭");
+}
 
-    let total: f64 = results.iter().map(|d| d.portfolio_total).sum();
-    let timestamp = chrono::Utc::now().timestamp_millis();
+#[utoipa::path(get, path = "/api/health", responses((status = 200, body = response::HealthResponse)))]
+async fn health() -> Json<response::HealthResponse> {
+    Json(response::HealthResponse { status: "ok" })
+}
 
-    // 更新缓存
+// 就绪检查：数据库 SELECT 1 和 RPC eth_blockNumber 都成功时返回 200，否则 503；每项最多等待 READY_CHECK_TIMEOUT
+#[utoipa::path(get, path = "/api/health/ready", responses(
+    (status = 200, description = "ready、warmed_up 以及 database / rpc 各项检查的结果和耗时"),
+    (status = 503, description = "数据库或 RPC 检查未通过，格式同 200"),
+))]
+async fn health_ready(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    const READY_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    async fn bounded<T>(check: impl std::future::Future<Output = Result<T, AppError>>) -> Result<T, AppError> {
+        tokio::time::timeout(READY_CHECK_TIMEOUT, check)
+            .await
+            .unwrap_or_else(|_| Err(AppError::ApiError(format!("超过 {} 秒未响应", READY_CHECK_TIMEOUT.as_secs()), None)))
+    }
+
+    let started = std::time::Instant::now();
+    let database = admin::check_result("database", started, bounded(db::ping(&state.db_pool)).await);
+
+    let started = std::time::Instant::now();
+    let block = bounded(state.portfolio.block_number()).await;
+    let block_number = block.as_ref().ok().copied();
+    let mut rpc = admin::check_result("rpc", started, block.map(|_| ()));
+    rpc["block_number"] = serde_json::json!(block_number);
+
+    let ready = database["passed"] == true && rpc["passed"] == true;
+    let status = if ready {
+        axum::http::StatusCode::OK
+    } else {
+        tracing::warn!("就绪检查未通过: database={} rpc={}", database["passed"], rpc["passed"]);
+        axum::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(serde_json::json!({
+        "ready": ready,
+        "warmed_up": state.warmed_up.load(std::sync::atomic::Ordering::Relaxed),
+        "checks": [database, rpc]
+    })))
+}
+
+#[utoipa::path(get, path = "/api/wallets", responses((status = 200, body = [WalletConfig])))]
+async fn get_wallets(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    format: encoding::Format,
+) -> axum::response::Response {
+    let mut wallets = state.wallets().await;
+    labels::apply_labels(&state, &mut wallets).await;
+    format.respond(&wallets)
+}
+
+// 一次 multicall 查询所有非模拟钱包的代理地址和签名地址的 USDC 余额；失败时返回空表，各钱包再单独查询
+async fn prefetch_usdc_balances(service: &PortfolioService, wallets: &[WalletConfig]) -> portfolio::UsdcBalances {
+    let mut addresses: Vec<alloy::primitives::Address> = wallets
+        .iter()
+        .filter(|w| !w.paper)
+        .flat_map(|w| std::iter::once(w.proxy_address.as_str()).chain(w.signer_address.as_deref()))
+        .filter_map(|address| address.parse().ok())
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+
+    match service.get_usdc_balances_batch(&addresses).await {
+        Ok(balances) => addresses.into_iter().zip(balances).collect(),
+        Err(e) => {
+            tracing::warn!(error = %e, "批量查询 USDC 余额失败，改为各钱包单独查询");
+            portfolio::UsdcBalances::new()
+        }
+    }
+}
+
+// 刷新所有未暂停的钱包，最多 concurrency 个钱包同时请求；结果写入数据库、缓存和实时推送，按钱包配置顺序返回
+async fn refresh_all(
+    state: &SharedState,
+    components: portfolio::Components,
+    concurrency: usize,
+) -> Result<Vec<PortfolioData>, AppError> {
+    let service = &state.portfolio;
+
+    // 只刷新部分数据或某一部分获取失败时，该部分沿用缓存；只刷新部分数据时，缓存中没有的钱包再用数据库中的最新快照
+    let mut previous = state.cache.read().await.clone();
+    if components != portfolio::Components::ALL
+        && state.wallets.read().await.iter().any(|w| !previous.contains_key(&w.proxy_address))
     {
+        for snapshot in db::get_latest_snapshots(&state.db_pool).await? {
+            previous.entry(snapshot.proxy_address.clone()).or_insert_with(|| snapshot.to_portfolio_data());
+        }
+    }
+
+    let paused = state.paused.read().await.clone();
+    let wallets: Vec<WalletConfig> = state.wallets().await.into_iter().filter(|w| !paused.contains(&w.wallet_id)).collect();
+    let started = std::time::Instant::now();
+    let wallet_count = wallets.len();
+    let prefetched = if components.usdc && state.settings.usdc_multicall {
+        prefetch_usdc_balances(service, &wallets).await
+    } else {
+        portfolio::UsdcBalances::new()
+    };
+
+    let results: Vec<PortfolioData> = for_each_wallet(wallets, concurrency, |wallet| {
+        let service = &service;
+        let previous = &previous;
+        let prefetched = &prefetched;
+        async move {
+            match service.fetch_portfolio(&wallet, components, previous.get(&wallet.proxy_address), prefetched).await {
+                Ok(data) => {
+                    save_refreshed(state, &wallet, &data).await;
+                    Some(data)
+                }
+                Err(e) => {
+                    tracing::error!(proxy_address = %wallet.proxy_address, wallet_name = %wallet.name, error = %e, "获取钱包数据失败");
+                    telemetry::record_refresh(&wallet.proxy_address, false, None);
+                    state.errors.record("refresh", Some(&wallet.proxy_address), &e);
+                    None
+                }
+            }
+        }
+    }).await;
+
+    tracing::info!(
+        wallets = wallet_count,
+        succeeded = results.len(),
+        portfolio_total = %results.iter().map(|d| d.portfolio_total).sum::<rust_decimal::Decimal>(),
+        latency_ms = started.elapsed().as_millis() as u64,
+        "刷新完成"
+    );
+
+    // 更新缓存，替换前的值用于价值变化告警
+    let replaced = {
         let mut cache = state.cache.write().await;
+        let mut replaced = std::collections::HashMap::new();
         for data in &results {
-            cache.insert(data.proxy_address.clone(), data.clone());
+            if let Some(old) = cache.insert(data.proxy_address.clone(), data.clone()) {
+                replaced.insert(data.proxy_address.clone(), old);
+            }
         }
+        replaced
+    };
+    if !state.settings.alert_threshold_pct.is_zero() {
+        let state = state.clone();
+        let results = results.clone();
+        tokio::spawn(async move {
+            notify::alert_value_changes(&state.notifier, &state.settings, &state.db_pool, &replaced, &results).await;
+        });
+    }
+    if results.iter().any(|d| !d.paper) {
+        state.last_refresh_success.store(chrono::Utc::now().timestamp_millis(), std::sync::atomic::Ordering::Relaxed);
+    }
+    state.live.publish(&results);
+    if state.settings.ctf_verify {
+        spawn_ctf_verification(state.clone(), &results);
     }
 
-    Json(serde_json::json!({
-        "success": true,
-        "data": results,
-        "total": total,
-        "timestamp": timestamp
-    }))
+    Ok(results)
 }
 
-async fn get_cached(
-    axum::extract::State(state): axum::extract::State<SharedState>,
-) -> Json<serde_json::Value> {
-    // 先尝试从内存缓存读取
-    let cache = state.cache.read().await;
-    if !cache.is_empty() {
-        let wallets: Vec<_> = cache.values().cloned().collect();
-        let total: f64 = wallets.iter().map(|d| d.portfolio_total).sum();
-        let total_usdc: f64 = wallets.iter().map(|d| d.usdc_balance).sum();
-        let total_positions: f64 = wallets.iter().map(|d| d.positions_value).sum();
-        return Json(serde_json::json!({
-            "wallets": wallets,
-            "total_portfolio": total,
-            "total_usdc_balance": total_usdc,
-            "total_positions_value": total_positions
-        }));
+// 同时最多处理 concurrency 个钱包，结果按钱包顺序返回；返回 None 的钱包（获取失败）被跳过，不影响其他钱包
+async fn for_each_wallet<T, F, Fut>(wallets: Vec<WalletConfig>, concurrency: usize, handle: F) -> Vec<T>
+where
+    F: FnMut(WalletConfig) -> Fut,
+    Fut: std::future::Future<Output = Option<T>>,
+{
+    use futures::StreamExt;
+
+    futures::stream::iter(wallets)
+        .map(handle)
+        .buffered(concurrency.max(1))
+        .filter_map(|result| async move { result })
+        .collect()
+        .await
+}
+
+// 单个钱包刷新成功后记录指标并保存快照，保存成功时发布到 NATS，写入失败时加入重试队列。
+// 部分数据获取失败（partial）的结果只更新缓存，不写入数据库，避免故障期间的沿用值或 0 进入历史。
+// 内存历史缓冲区只记录真正写入数据库的快照（去重跳过的不算，重试成功后再补上），与数据库查询的结果保持一致
+async fn save_refreshed(state: &AppState, wallet: &WalletConfig, data: &PortfolioData) {
+    telemetry::record_refresh(&data.proxy_address, true, Some(data.portfolio_total));
+    if data.partial {
+        tracing::warn!(proxy_address = %data.proxy_address, wallet_name = %wallet.name, "部分数据获取失败，本次不保存快照");
+        return;
     }
-    drop(cache);
+    match save_snapshot_bounded(state, data).await {
+        Ok(saved) => {
+            if saved {
+                state.recent_history.push(std::slice::from_ref(data));
+            }
+            state.publisher.publish(data);
+        }
+        Err(e) => {
+            tracing::error!(proxy_address = %data.proxy_address, wallet_name = %wallet.name, error = %e, "保存快照失败，加入重试队列");
+            state.errors.record("save_snapshot", Some(&data.proxy_address), &e);
+            state.save_retries.enqueue(data.clone());
+        }
+    }
+}
 
-    // 内存缓存为空，从数据库读取最新快照
+// 启动预热：先用数据库中的最新快照填充缓存，让读接口立即有数据，再以 WARMUP_CONCURRENCY 做第一次完整刷新。
+// 两步都完成后才标记 warmed_up，就绪检查据此判断；预热并发单独配置，避免启动时集中请求压垮 RPC
+async fn warmup(state: SharedState) {
     match db::get_latest_snapshots(&state.db_pool).await {
         Ok(snapshots) => {
-            let wallets: Vec<PortfolioData> = snapshots.iter().map(|s| PortfolioData {
-                proxy_address: s.proxy_address.clone(),
-                usdc_balance: s.usdc_balance.to_string().parse().unwrap_or(0.0),
-                positions_value: s.positions_value.to_string().parse().unwrap_or(0.0),
-                portfolio_total: s.portfolio_total.to_string().parse().unwrap_or(0.0),
-                last_updated: s.timestamp.timestamp_millis(),
-            }).collect();
-            
-            let total: f64 = wallets.iter().map(|d| d.portfolio_total).sum();
-            let total_usdc: f64 = wallets.iter().map(|d| d.usdc_balance).sum();
-            let total_positions: f64 = wallets.iter().map(|d| d.positions_value).sum();
-            
-            Json(serde_json::json!({
-                "wallets": wallets,
-                "total_portfolio": total,
-                "total_usdc_balance": total_usdc,
-                "total_positions_value": total_positions
-            }))
+            let mut cache = state.cache.write().await;
+            for snapshot in &snapshots {
+                cache.entry(snapshot.proxy_address.clone()).or_insert_with(|| snapshot.to_portfolio_data());
+            }
+            tracing::info!("已从数据库预热 {} 个钱包的缓存", snapshots.len());
         }
         Err(e) => {
-            tracing::error!("从数据库读取缓存失败: {}", e);
-            Json(serde_json::json!({
-                "wallets": [],
-                "total_portfolio": 0,
-                "total_usdc_balance": 0,
-                "total_positions_value": 0
-            }))
+            tracing::warn!("从数据库预热缓存失败: {}", e);
+            state.errors.record("warmup", None, &e);
+        }
+    }
+
+    if state.settings.warmup_refresh {
+        let started = std::time::Instant::now();
+        match refresh_all(&state, portfolio::Components::ALL, state.settings.warmup_concurrency).await {
+            Ok(results) => tracing::info!(
+                "启动刷新完成: {} 个钱包, 并发 {}, 耗时 {} ms",
+                results.len(), state.settings.warmup_concurrency, started.elapsed().as_millis()
+            ),
+            Err(e) => tracing::warn!("启动刷新失败: {}", e),
+        }
+    }
+
+    state.warmed_up.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+// 按 REFRESH_INTERVAL_SECS 定时刷新所有钱包，保持缓存和数据库持续更新；第一次在一个间隔之后执行，启动时的刷新由 warmup 负责
+async fn background_refresh(state: SharedState) {
+    let period = std::time::Duration::from_secs(state.settings.refresh_interval_secs);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    // 刷新耗时超过间隔时顺延，而不是连续补跑
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    tracing::info!("后台定时刷新已开启，间隔 {} 秒", state.settings.refresh_interval_secs);
+
+    loop {
+        interval.tick().await;
+        let started = std::time::Instant::now();
+        match refresh_all(&state, portfolio::Components::ALL, state.settings.refresh_concurrency).await {
+            Ok(results) => tracing::info!(
+                "后台刷新完成: {} 个钱包, 耗时 {} ms", results.len(), started.elapsed().as_millis()
+            ),
+            Err(e) => {
+                tracing::warn!("后台刷新失败: {}", e);
+                state.errors.record("background_refresh", None, &e);
+            }
+        }
+    }
+}
+
+#[utoipa::path(get, path = "/api/portfolio/refresh", params(RefreshQuery), responses(
+    (status = 200, body = response::RefreshResponse),
+    (status = 429, description = "距上次刷新不足 REFRESH_COOLDOWN_SECS，Retry-After 给出等待秒数"),
+))]
+async fn refresh_portfolio(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<RefreshQuery>,
+    format: encoding::Format,
+) -> Result<axum::response::Response, AppError> {
+    let components = portfolio::Components::parse(query.components.as_deref())?;
+    let arrived = std::time::Instant::now();
+    let mut last = state.last_refresh.lock().await;
+
+    let shared = match last.as_ref() {
+        // 请求到达时已有刷新在进行，直接使用那次的结果
+        Some(prev) if prev.finished >= arrived && prev.components == components && prev.results.is_some() => {
+            prev.results.clone()
+        }
+        Some(prev) => {
+            let cooldown = std::time::Duration::from_secs(state.settings.refresh_cooldown_secs);
+            let elapsed = prev.finished.elapsed();
+            if elapsed < cooldown {
+                return Ok(too_many_refreshes((cooldown - elapsed).as_secs().max(1)));
+            }
+            None
+        }
+        None => None,
+    };
+
+    let results = match shared {
+        Some(results) => {
+            tracing::info!(wallets = results.len(), "合并到进行中的刷新");
+            results
+        }
+        None => {
+            let refreshed = refresh_all(&state, components, state.settings.refresh_concurrency).await;
+            *last = Some(LastRefresh {
+                finished: std::time::Instant::now(),
+                components,
+                results: refreshed.as_ref().ok().cloned(),
+            });
+            refreshed?
+        }
+    };
+    drop(last);
+
+    let total: rust_decimal::Decimal = results.iter().map(|d| d.portfolio_total).sum();
+    let total_positions_count = total_positions_count(&results);
+    let timestamp = chrono::Utc::now().timestamp_millis();
+
+    let results = with_display(&state, results).await;
+
+    Ok(format.respond(&response::RefreshResponse {
+        success: true,
+        data: results,
+        total,
+        total_positions_count,
+        timestamp,
+    }))
+}
+
+fn too_many_refreshes(retry_after: u64) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    (
+        axum::http::StatusCode::TOO_MANY_REQUESTS,
+        [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+        Json(serde_json::json!({
+            "error": format!("刷新过于频繁，请 {} 秒后再试", retry_after),
+            "retry_after": retry_after
+        })),
+    ).into_response()
+}
+
+// 同一地址距上次开始刷新不足 cooldown 时返回需要等待的秒数，否则记下本次的开始时间并返回 None；
+// 记录的是开始时间，进行中的刷新同样会挡住重复请求。顺带清理已过冷却期的记录
+fn claim_wallet_refresh(
+    started: &mut std::collections::HashMap<String, std::time::Instant>,
+    address: &str,
+    cooldown: std::time::Duration,
+) -> Option<u64> {
+    if let Some(remaining) = started.get(address).and_then(|at| cooldown.checked_sub(at.elapsed())) {
+        if !remaining.is_zero() {
+            // 向上取整，客户端按 Retry-After 等待后一定已过冷却期
+            return Some(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0));
+        }
+    }
+    started.retain(|_, at| at.elapsed() < cooldown);
+    started.insert(address.to_string(), std::time::Instant::now());
+    None
+}
+
+// 只刷新一个钱包并更新它的缓存；同一地址受 REFRESH_COOLDOWN_SECS 限制，与全量刷新分开计算。
+// 暂停的钱包同样刷新，未跟踪的地址返回 404
+#[utoipa::path(post, path = "/api/portfolio/refresh/{address}", params(("address" = String, Path, description = "代理地址")), responses(
+    (status = 200, body = PortfolioData),
+    (status = 404, description = "未跟踪的钱包地址"),
+    (status = 429, description = "该地址距上次刷新不足 REFRESH_COOLDOWN_SECS，Retry-After 给出等待秒数"),
+))]
+async fn refresh_wallet(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Path(address): Path<String>,
+    format: encoding::Format,
+) -> Result<axum::response::Response, AppError> {
+    use axum::response::IntoResponse;
+
+    let address = config::canonical_address(address.trim());
+    let wallet = state.wallets.read().await.iter().find(|w| w.proxy_address.eq_ignore_ascii_case(&address)).cloned();
+    let Some(wallet) = wallet else {
+        return Ok((
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("未跟踪的钱包地址: {}", address) })),
+        ).into_response());
+    };
+
+    let cooldown = std::time::Duration::from_secs(state.settings.refresh_cooldown_secs);
+    let throttled = claim_wallet_refresh(&mut state.wallet_refreshes.lock().unwrap(), &wallet.proxy_address, cooldown);
+    if let Some(retry_after) = throttled {
+        return Ok(too_many_refreshes(retry_after));
+    }
+
+    let previous = state.cache.read().await.get(&wallet.proxy_address).cloned();
+    let data = match state.portfolio.fetch_portfolio(&wallet, portfolio::Components::ALL, previous.as_ref(), &portfolio::UsdcBalances::new()).await {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::error!(proxy_address = %wallet.proxy_address, wallet_name = %wallet.name, error = %e, "获取钱包数据失败");
+            telemetry::record_refresh(&wallet.proxy_address, false, None);
+            state.errors.record("refresh", Some(&wallet.proxy_address), &e);
+            return Err(e);
+        }
+    };
+    save_refreshed(&state, &wallet, &data).await;
+
+    state.cache.write().await.insert(data.proxy_address.clone(), data.clone());
+    if !data.paper {
+        state.last_refresh_success.store(chrono::Utc::now().timestamp_millis(), std::sync::atomic::Ordering::Relaxed);
+    }
+    state.live.publish(std::slice::from_ref(&data));
+
+    let data = with_display(&state, vec![data]).await.remove(0);
+    Ok(format.respond(&data))
+}
+
+// 在后台核对链上 CTF 余额，不阻塞刷新响应
+fn spawn_ctf_verification(state: SharedState, results: &[PortfolioData]) {
+    let addresses: Vec<String> = results.iter().filter(|d| !d.paper).map(|d| d.proxy_address.clone()).collect();
+    tokio::spawn(async move {
+        let service = &state.portfolio;
+        for address in addresses {
+            match service.verify_ctf_positions(&address).await {
+                Ok(report) => {
+                    if !report.discrepancies.is_empty() {
+                        tracing::warn!("钱包 {} 的 data-api 持仓与链上 CTF 余额不一致: {:?}", address, report.discrepancies);
+                    }
+                    state.ctf_reports.write().await.insert(address, report);
+                }
+                Err(e) => {
+                    tracing::warn!("核对钱包 {} 的 CTF 余额失败: {}", address, e);
+                    state.errors.record("ctf_verify", Some(&address), &e);
+                }
+            }
+        }
+    });
+}
+
+// 按钱包配置填充展示相关的字段：格式化金额、模拟钱包和暂停标记
+async fn with_display(state: &AppState, mut list: Vec<PortfolioData>) -> Vec<PortfolioData> {
+    let wallets = state.wallets.read().await;
+    let paused = state.paused.read().await;
+    for data in &mut list {
+        if let Some(wallet) = wallets.iter().find(|w| w.proxy_address == data.proxy_address) {
+            data.paper = wallet.paper;
+            data.paused = paused.contains(&wallet.wallet_id);
+            data.display = Some(format::display_values(wallet, data, &state.settings.fx_rates));
+        }
+    }
+    list
+}
+
+// 总价值、USDC 余额和持仓价值与该地址最新快照相差都不超过 DEDUP_EPSILON
+async fn unchanged_since_latest(state: &AppState, data: &PortfolioData) -> Result<bool, AppError> {
+    let Some(latest) = db::get_latest_snapshot_for(&state.db_pool, &data.proxy_address).await? else {
+        return Ok(false);
+    };
+    let epsilon = state.settings.dedup_epsilon;
+    let close = |a: rust_decimal::Decimal, b: rust_decimal::Decimal| (a - b).abs() <= epsilon;
+    Ok(close(latest.portfolio_total, data.portfolio_total)
+        && close(latest.usdc_balance, data.usdc_balance)
+        && close(latest.positions_value, data.positions_value))
+}
+
+// 快照对应的 token_balances 行，只包含 data.token_balances 中有值的代币；正常写入和重试补写共用
+fn snapshot_balances(service: &PortfolioService, data: &PortfolioData) -> Vec<db::TokenBalance> {
+    service
+        .tokens()
+        .filter_map(|token| {
+            let balance = *data.token_balances.get(&token.symbol)?;
+            Some(db::TokenBalance {
+                token_address: format!("{:#x}", token.address),
+                symbol: token.symbol.clone(),
+                balance,
+            })
+        })
+        .collect()
+}
+
+// 刷新时的数据库写入受 refresh_db_permits 限制，给读接口留出连接池余量；返回是否写入了新的快照
+async fn save_snapshot_bounded(state: &AppState, data: &PortfolioData) -> Result<bool, AppError> {
+    let _permit = match state.refresh_db_permits.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            tracing::info!("刷新数据库并发达到上限 {}，等待空闲连接", state.settings.refresh_db_concurrency);
+            state.refresh_db_permits.acquire().await
+                .map_err(|e| AppError::DbError(format!("{}", e)))?
+        }
+    };
+
+    if state.settings.dedup_snapshots && unchanged_since_latest(state, data).await? {
+        tracing::debug!(proxy_address = %data.proxy_address, "金额与最新快照相同，跳过写入");
+        return Ok(false);
+    }
+
+    if data.token_balances.is_empty() {
+        db::save_snapshot(
+            &state.db_pool,
+            &data.proxy_address,
+            data.portfolio_total,
+            data.usdc_balance,
+            data.positions_value,
+        ).await?;
+        return Ok(true);
+    }
+
+    let balances = snapshot_balances(&state.portfolio, data);
+    db::save_snapshot_with_balances(
+        &state.db_pool,
+        &data.proxy_address,
+        data.portfolio_total,
+        data.usdc_balance,
+        data.positions_value,
+        &balances,
+    ).await?;
+    Ok(true)
+}
+
+#[utoipa::path(get, path = "/api/portfolio/cached", params(CachedQuery), responses((status = 200, body = response::CachedResponse)))]
+async fn get_cached(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<CachedQuery>,
+    format: encoding::Format,
+) -> Result<axum::response::Response, AppError> {
+    Ok(format.respond(&cached_overview(&state, None, query.hide_dust).await?))
+}
+
+// 单个钱包的数据：先查内存缓存，没有时用数据库中该地址的最新快照；未跟踪的地址返回 404
+#[utoipa::path(get, path = "/api/portfolio/wallet/{address}", params(("address" = String, Path, description = "代理地址")), responses(
+    (status = 200, body = PortfolioData),
+    (status = 404, description = "未跟踪的钱包地址"),
+))]
+async fn get_wallet_portfolio(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Path(address): Path<String>,
+    format: encoding::Format,
+) -> Result<axum::response::Response, AppError> {
+    use axum::response::IntoResponse;
+
+    let address = config::canonical_address(address.trim());
+    let not_found = || (
+        axum::http::StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": format!("未跟踪的钱包地址: {}", address) })),
+    ).into_response();
+
+    if !state.wallets.read().await.iter().any(|w| w.proxy_address.eq_ignore_ascii_case(&address)) {
+        return Ok(not_found());
+    }
+
+    let cached = state.cache.read().await.get(&address).cloned();
+    let data = match cached {
+        Some(data) => data,
+        None => match db::get_latest_snapshot_for(&state.db_pool, &address).await? {
+            Some(snapshot) => snapshot.to_portfolio_data(),
+            None => return Ok(not_found()),
+        },
+    };
+
+    let data = with_display(&state, vec![data]).await.remove(0);
+    Ok(format.respond(&data))
+}
+
+// 当前总价值为 0 且最近 INACTIVE_LOOKBACK_SNAPSHOTS 条快照也都为 0 的钱包；快照不足这么多条的不算，模拟钱包不检查。
+// 查询失败只记录日志，不影响响应
+async fn inactive_wallets(state: &AppState, wallets: &[PortfolioData]) -> std::collections::HashSet<String> {
+    let lookback = state.settings.inactive_lookback_snapshots;
+    let mut inactive = std::collections::HashSet::new();
+    if lookback == 0 {
+        return inactive;
+    }
+    for data in wallets.iter().filter(|d| !d.paper && d.portfolio_total.is_zero()) {
+        match db::count_nonzero_snapshots(&state.db_pool, &data.proxy_address, lookback).await {
+            Ok((total, nonzero)) if total >= lookback && nonzero == 0 => {
+                inactive.insert(data.proxy_address.clone());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(proxy_address = %data.proxy_address, error = %e, "检查钱包是否无活动失败"),
         }
     }
+    inactive
+}
+
+// 所有缓存都已过期时在后台刷新一次，当前请求仍直接返回过期数据
+fn spawn_stale_refresh(state: &SharedState) {
+    use std::sync::atomic::Ordering;
+
+    if state.stale_refresh_running.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    tracing::info!("缓存已全部过期，触发后台刷新");
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = refresh_all(&state, portfolio::Components::ALL, state.settings.refresh_concurrency).await {
+            tracing::error!("过期缓存的后台刷新失败: {}", e);
+        }
+        state.stale_refresh_running.store(false, Ordering::Release);
+    });
+}
+
+// 构建缓存概览，wallets_filter 为 None 时返回全部钱包；缓存为空且数据库读取失败时返回错误，而不是空列表
+async fn cached_overview(state: &SharedState, wallets_filter: Option<&[String]>, hide_dust: bool) -> Result<response::CachedResponse, AppError> {
+    // 先尝试从内存缓存读取
+    let cache = state.cache.read().await;
+    let mut wallets: Vec<PortfolioData> = if !cache.is_empty() {
+        cache.values().filter(|d| history::matches_filter(wallets_filter, &d.proxy_address)).cloned().collect()
+    } else {
+        drop(cache);
+
+        // 内存缓存为空，从数据库读取最新快照
+        let snapshots = db::get_latest_snapshots(&state.db_pool).await.map_err(|e| {
+            tracing::error!("从数据库读取缓存失败: {}", e);
+            state.errors.record("cached", None, &e);
+            e
+        })?;
+        snapshots.iter()
+            .filter(|s| history::matches_filter(wallets_filter, &s.proxy_address))
+            .map(|s| s.to_portfolio_data())
+            .collect()
+    };
+
+    let total: rust_decimal::Decimal = wallets.iter().map(|d| d.portfolio_total).sum();
+    let total_usdc: rust_decimal::Decimal = wallets.iter().map(|d| d.usdc_balance).sum();
+    let total_positions: rust_decimal::Decimal = wallets.iter().map(|d| d.positions_value).sum();
+    let positions_count = total_positions_count(&wallets);
+
+    let now = chrono::Utc::now().timestamp_millis();
+    for data in &mut wallets {
+        let (age, stale) = state.staleness(data, now);
+        data.age_seconds = Some(age);
+        data.stale = stale;
+    }
+    if !wallets.is_empty() && wallets.iter().all(|d| d.stale) {
+        spawn_stale_refresh(state);
+    }
+    // 合计已经算完，隐藏零头钱包只影响返回的列表
+    for data in &mut wallets {
+        data.dust = state.is_dust(data.portfolio_total);
+    }
+    let before = wallets.len();
+    if hide_dust {
+        wallets.retain(|d| !d.dust);
+    }
+    let dust_hidden = before - wallets.len();
+    let inactive = inactive_wallets(state, &wallets).await;
+    for data in &mut wallets {
+        data.inactive = inactive.contains(&data.proxy_address);
+    }
+    let wallets = with_display(state, wallets).await;
+
+    Ok(response::CachedResponse {
+        wallets,
+        total_portfolio: total,
+        total_usdc_balance: total_usdc,
+        total_positions_value: total_positions,
+        total_positions_count: positions_count,
+        dust_hidden,
+    })
 }
 
+// 最近 hours 小时内超过 ALERT_THRESHOLD_PCT 的价值变化，最新的在前
+#[utoipa::path(get, path = "/api/portfolio/alerts", params(AlertsQuery), responses((status = 200, body = [response::AlertEntry])))]
+async fn get_alerts(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<AlertsQuery>,
+    format: encoding::Format,
+) -> Result<axum::response::Response, AppError> {
+    let hours = query.hours.unwrap_or(24).max(1);
+    let alerts = db::get_alerts(&state.db_pool, hours).await?;
+
+    let wallets = state.wallets.read().await;
+    let entries: Vec<response::AlertEntry> = alerts
+        .into_iter()
+        .map(|alert| response::AlertEntry {
+            timestamp: alert.timestamp.timestamp_millis(),
+            name: wallets.iter().find(|w| w.proxy_address.eq_ignore_ascii_case(&alert.proxy_address)).map(|w| w.name.clone()),
+            proxy_address: alert.proxy_address,
+            old_value: alert.old_value,
+            new_value: alert.new_value,
+            pct_change: alert.pct_change,
+        })
+        .collect();
+    Ok(format.respond(&entries))
+}
+
+// 所有钱包都没有持仓数量时返回 None，而不是 0
+fn total_positions_count(list: &[PortfolioData]) -> Option<usize> {
+    list.iter().filter_map(|d| d.positions_count).reduce(|a, b| a + b)
+}
+
+#[utoipa::path(get, path = "/api/portfolio/history", params(HistoryQuery), responses(
+    (status = 200, description = "按分组的历史数据；X-Total-Count 为总行数，X-Next-Cursor 为下一次增量请求的 since"),
+))]
 async fn get_history(
     axum::extract::State(state): axum::extract::State<SharedState>,
     Query(query): Query<HistoryQuery>,
-) -> Json<serde_json::Value> {
+    format: encoding::Format,
+) -> Result<axum::response::Response, AppError> {
+    use axum::response::IntoResponse;
+
     let hours = query.hours.unwrap_or(24); // 默认24小时
     
-    match db::get_history(&state.db_pool, hours).await {
-        Ok(snapshots) => {
-            // 按时间戳分组，构建前端需要的格式
-            let mut grouped: std::collections::BTreeMap<i64, std::collections::HashMap<String, f64>> = std::collections::BTreeMap::new();
-            
-            for snapshot in snapshots {
-                let ts = snapshot.timestamp.timestamp_millis();
-                // 按分钟取整
-                let ts_rounded = (ts / 60000) * 60000;
-                
-                let entry = grouped.entry(ts_rounded).or_default();
-                entry.insert(
-                    snapshot.proxy_address,
-                    snapshot.usdc_balance.to_string().parse().unwrap_or(0.0)
-                );
-            }
-            
-            let history: Vec<_> = grouped.into_iter().map(|(timestamp, wallets)| {
-                let total: f64 = wallets.values().sum();
-                serde_json::json!({
-                    "timestamp": timestamp,
-                    "total": total,
-                    "wallets": wallets
-                })
-            }).collect();
-            
-            Json(serde_json::json!(history))
+    // 短窗口优先从内存缓冲区读取；窗口内没有数据时返回 200 []，数据库出错时返回错误状态码而不是伪装成空数组
+    let from_ms = query.since.map(|s| s + 1)
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_millis() - hours * 3_600_000);
+    // 分页按快照行进行，同一分钟的快照可能分在相邻两页，前端合并时按 timestamp 归并
+    let limit = query.limit.unwrap_or(state.settings.history_page_size).clamp(1, state.settings.history_max_page_size);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let addresses = history::parse_address_list(query.addresses.as_deref());
+    let (snapshots, total_count) = match state.recent_history.window(from_ms) {
+        Some(recent) => {
+            let recent: Vec<_> = recent.into_iter()
+                .filter(|d| history::matches_filter(addresses.as_deref(), &d.proxy_address))
+                .collect();
+            let total = recent.len() as i64;
+            let page = recent.iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .map(db::PortfolioSnapshot::from_portfolio_data)
+                .collect();
+            (page, total)
         }
-        Err(e) => {
+        None => db::get_history_page(&state.db_pool, hours, query.since, addresses.as_deref(), limit, offset).await.map_err(|e| {
             tracing::error!("获取历史数据失败: {}", e);
-            Json(serde_json::json!([]))
+            state.errors.record("history", None, &e);
+            e
+        })?,
+    };
+    let policy = state.settings.history_invalid_timestamps;
+    let snapshots: Vec<_> = snapshots.into_iter()
+        .filter_map(|s| history::sanitize_snapshot(s, policy))
+        .collect();
+
+    // 下一次增量请求的游标：本次返回的最大原始时间戳，没有新数据时沿用传入的游标
+    let next_cursor = snapshots.iter()
+        .map(|s| s.timestamp.timestamp_millis())
+        .max()
+        .or(query.since)
+        .unwrap_or(0);
+
+    if query.raw {
+        // 超过行数上限时只保留最新的部分
+        let cap = state.settings.history_raw_max_rows;
+        let truncated = snapshots.len() > cap;
+        let rows: Vec<_> = snapshots[snapshots.len().saturating_sub(cap)..]
+            .iter()
+            .map(|s| serde_json::json!({
+                "timestamp": s.timestamp.timestamp_millis(),
+                "proxy_address": s.proxy_address,
+                "portfolio_total": s.portfolio_total,
+                "usdc_balance": s.usdc_balance,
+                "positions_value": s.positions_value,
+                "backfilled": s.backfilled
+            }))
+            .collect();
+
+        return Ok((
+            [
+                ("X-Next-Cursor", next_cursor.to_string()),
+                ("X-Truncated", truncated.to_string()),
+                ("X-Total-Count", total_count.to_string()),
+            ],
+            format.respond(&rows),
+        ).into_response());
+    }
+
+    // 按时间戳分组，构建前端需要的格式
+    let bucket = Bucket::parse(query.bucket.as_deref());
+    let agg = history::Aggregation::parse(query.agg.as_deref());
+    let mut grouped: std::collections::BTreeMap<i64, (std::collections::HashMap<String, history::BucketValue>, bool)> = std::collections::BTreeMap::new();
+    
+    for snapshot in snapshots {
+        let ts = snapshot.timestamp.timestamp_millis();
+        let ts_rounded = bucket.start_of(ts);
+        
+        let entry = grouped.entry(ts_rounded).or_default();
+        entry.0
+            .entry(snapshot.proxy_address)
+            .or_default()
+            .add(snapshot.usdc_balance);
+        entry.1 |= snapshot.backfilled;
+    }
+    
+    let history: Vec<_> = grouped.into_iter()
+        .map(|(timestamp, (wallets, backfilled))| bucket_entry(timestamp, wallets, backfilled, agg))
+        .collect();
+    
+    Ok((
+        [
+            ("X-Next-Cursor", next_cursor.to_string()),
+            ("X-Total-Count", total_count.to_string()),
+        ],
+        format.respond(&history),
+    ).into_response())
+}
+
+#[utoipa::path(get, path = "/api/portfolio/positions/{address}", params(("address" = String, Path, description = "代理地址"), PositionsQuery), responses(
+    (status = 200, body = response::PositionsResponse),
+    (status = 400, description = "地址或 sort 参数无效"),
+    (status = 404, description = "未跟踪的钱包地址"),
+))]
+async fn get_positions(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Path(address): Path<String>,
+    Query(query): Query<PositionsQuery>,
+) -> Result<axum::response::Response, AppError> {
+    use axum::response::IntoResponse;
+
+    let sort = portfolio::PositionSort::parse(query.sort.as_deref())?;
+    let address = config::normalize_address(&address)?;
+    // 只查询已跟踪的钱包，避免服务被当成任意地址的 data-api 代理
+    if !state.wallets.read().await.iter().any(|w| w.proxy_address.eq_ignore_ascii_case(&address)) {
+        return Ok((
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("未跟踪的钱包地址: {}", address) })),
+        ).into_response());
+    }
+
+    let mut positions = state.portfolio.get_positions(&address).await.map_err(|e| {
+        tracing::error!("获取钱包 {} 持仓失败: {}", address, e);
+        state.errors.record("positions", Some(&address), &e);
+        e
+    })?;
+    let total = positions.len();
+
+    if let Some(min_value) = query.min_value {
+        positions.retain(|p| p.current_value >= min_value);
+    }
+    if let Some(sort) = sort {
+        sort.apply(&mut positions);
+    }
+    if let Some(limit) = query.limit {
+        positions.truncate(limit);
+    }
+
+    Ok(Json(response::PositionsResponse {
+        proxy_address: address,
+        total,
+        positions,
+    }).into_response())
+}
+
+#[utoipa::path(get, path = "/api/positions/by-market/{condition_id}", params(("condition_id" = String, Path, description = "市场的 condition id")), responses(
+    (status = 200, description = "各钱包在该市场的持仓、价值和数量合计；有钱包获取失败时 partial 为 true，地址列在 failed 中"),
+))]
+async fn get_positions_by_market(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Path(condition_id): Path<String>,
+) -> Json<serde_json::Value> {
+    let service = &state.portfolio;
+    let mut breakdown = Vec::new();
+    let mut total_value = rust_decimal::Decimal::ZERO;
+    let mut total_size = rust_decimal::Decimal::ZERO;
+    // 获取持仓失败的钱包地址，此时合计不完整，partial 为 true
+    let mut failed = Vec::new();
+
+    for wallet in state.wallets().await.iter().filter(|w| !w.paper) {
+        let positions = match service.get_positions(&wallet.proxy_address).await {
+            Ok(positions) => positions,
+            Err(e) => {
+                tracing::error!("获取钱包 {} 持仓失败: {}", wallet.name, e);
+                state.errors.record("positions_by_market", Some(&wallet.proxy_address), &e);
+                failed.push(wallet.proxy_address.clone());
+                continue;
+            }
+        };
+
+        let matched: Vec<_> = positions
+            .into_iter()
+            .filter(|p| p.condition_id.eq_ignore_ascii_case(&condition_id))
+            .collect();
+        // 该市场没有持仓的钱包直接省略
+        if matched.is_empty() {
+            continue;
         }
+
+        let value: rust_decimal::Decimal = matched.iter().map(|p| p.current_value).sum();
+        let size: rust_decimal::Decimal = matched.iter().map(|p| p.size).sum();
+        total_value += value;
+        total_size += size;
+
+        breakdown.push(serde_json::json!({
+            "wallet_id": wallet.wallet_id,
+            "name": wallet.name,
+            "proxy_address": wallet.proxy_address,
+            "value": value,
+            "size": size,
+            "positions": matched
+        }));
+    }
+
+    Json(serde_json::json!({
+        "condition_id": condition_id,
+        "wallets": breakdown,
+        "total_value": total_value,
+        "total_size": total_size,
+        "partial": !failed.is_empty(),
+        "failed": failed
+    }))
+}
+
+#[utoipa::path(get, path = "/api/portfolio/approvals", params(ApprovalsQuery), responses(
+    (status = 200, description = "各交易合约的 USDC 授权额度和 CTF 授权状态，trade_ready 表示全部授权已完成；仅在 APPROVALS_CHECK 开启时提供"),
+))]
+async fn get_approvals(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<ApprovalsQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let service = &state.portfolio;
+
+    let wallet = config::canonical_address(&query.wallet);
+    let approvals = service.get_approvals(&wallet).await.map_err(|e| {
+        tracing::error!("查询钱包 {} 授权状态失败: {}", wallet, e);
+        state.errors.record("approvals", Some(&wallet), &e);
+        e
+    })?;
+    let trade_ready = approvals.iter().all(|a| a.usdc_approved && a.ctf_approved);
+    Ok(Json(serde_json::json!({
+        "wallet": wallet,
+        "trade_ready": trade_ready,
+        "approvals": approvals
+    })))
+}
+
+// 估算完成剩余授权所需的交易费用，只是估算值，实际费用取决于发送时的 gas 价格
+#[utoipa::path(get, path = "/api/portfolio/approvals/gas", params(ApprovalsQuery), responses(
+    (status = 200, description = "剩余授权交易的 gas 估算、gas 价格以及 POL / USD 费用；仅在 APPROVALS_CHECK 开启时提供"),
+))]
+async fn get_approval_gas(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<ApprovalsQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let service = &state.portfolio;
+    let wallet = config::canonical_address(&query.wallet);
+    let (estimates, gas_price) = service.estimate_approval_gas(&wallet).await?;
+
+    let total_gas: u64 = estimates.iter().filter_map(|e| e.gas).sum();
+    let cost_pol = gas_price.map(|price| total_gas as f64 * price as f64 / 1e18);
+    let cost_usd = cost_pol.zip(state.settings.pol_usd_price).map(|(pol, rate)| pol * rate);
+
+    Ok(Json(serde_json::json!({
+        "wallet": wallet,
+        "estimate": true,
+        "transactions": estimates,
+        "total_gas": total_gas,
+        "gas_price_wei": gas_price.map(|p| p.to_string()),
+        "cost_pol": cost_pol,
+        "cost_usd": cost_usd
+    })))
+}
+
+// 导出原始快照为 CSV，边从数据库读取边输出，适合很长的时间范围
+#[utoipa::path(get, path = "/api/portfolio/history.csv", params(HistoryCsvQuery), responses(
+    (status = 200, content_type = "text/csv", description = "timestamp、proxy_address 和三个金额列；读取出错时中断输出"),
+))]
+async fn get_history_csv(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<HistoryCsvQuery>,
+) -> axum::response::Response {
+    let hours = query.hours.unwrap_or(24);
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(16);
+    let pool = state.db_pool.clone();
+    let policy = state.settings.history_invalid_timestamps;
+    tokio::spawn(async move {
+        use futures::StreamExt;
+
+        let header = csv_row(["timestamp", "proxy_address", "portfolio_total", "usdc_balance", "positions_value"]);
+        if tx.send(header).await.is_err() {
+            return;
+        }
+
+        let mut rows = db::stream_history(&pool, hours, None);
+        while let Some(row) = rows.next().await {
+            let snapshot = match row {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    // 响应头已经发出，只能中断输出让客户端感知到下载不完整
+                    tracing::error!("导出历史 CSV 失败: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            let Some(snapshot) = history::sanitize_snapshot(snapshot, policy) else {
+                continue;
+            };
+            let line = csv_row([
+                snapshot.timestamp.to_rfc3339(),
+                snapshot.proxy_address,
+                snapshot.portfolio_total.to_string(),
+                snapshot.usdc_balance.to_string(),
+                snapshot.positions_value.to_string(),
+            ]);
+            if tx.send(line).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    axum::response::Response::builder()
+        .header(axum::http::header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(
+            axum::http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"portfolio-history-{}h.csv\"", hours),
+        )
+        .body(axum::body::Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+fn csv_row<I, T>(fields: I) -> Result<Vec<u8>, std::io::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    writer.into_inner().map_err(|e| e.into_error())
+}
+
+// 一次返回钱包配置、缓存和历史数据。历史部分按行从数据库流式读取并分组输出，不在内存中缓冲整个窗口
+#[utoipa::path(get, path = "/api/dashboard", params(DashboardQuery), responses(
+    (status = 200, description = "流式输出的 JSON：wallets 为钱包配置，cached 为各钱包缓存的组合数据，history 为按 bucket 聚合的历史记录"),
+))]
+async fn get_dashboard(
+    axum::extract::State(state): axum::extract::State<SharedState>,
+    Query(query): Query<DashboardQuery>,
+) -> Result<axum::response::Response, AppError> {
+    let hours = query.hours.unwrap_or(24);
+    let bucket = Bucket::parse(query.bucket.as_deref());
+    let agg = history::Aggregation::parse(query.agg.as_deref());
+    let wallets_filter = history::parse_address_list(query.wallets.as_deref());
+
+    let configs: Vec<_> = state.wallets().await.into_iter().filter(|w| history::matches_filter(wallets_filter.as_deref(), &w.proxy_address)).collect();
+    let cached = cached_overview(&state, wallets_filter.as_deref(), false).await?;
+
+    let head = format!(
+        "{{\"wallets\":{},\"cached\":{},\"history\":[",
+        serde_json::to_string(&configs).unwrap_or_else(|_| "[]".to_string()),
+        serde_json::to_string(&cached).unwrap_or_else(|_| "null".to_string())
+    );
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(16);
+    let pool = state.db_pool.clone();
+    let policy = state.settings.history_invalid_timestamps;
+    tokio::spawn(async move {
+        use futures::StreamExt;
+
+        if tx.send(Ok(head)).await.is_err() {
+            return;
+        }
+
+        let mut rows = db::stream_history(&pool, hours, wallets_filter.as_deref());
+        let mut current: Option<(i64, std::collections::HashMap<String, history::BucketValue>, bool)> = None;
+        let mut first = true;
+
+        // 行按时间升序返回，分组起点变化时输出上一个分组
+        while let Some(row) = rows.next().await {
+            let snapshot = match row {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    // 不补上结尾的 ]}，中断输出，避免客户端把截断的历史当成完整的 JSON
+                    tracing::error!("流式读取历史数据失败: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            let Some(snapshot) = history::sanitize_snapshot(snapshot, policy) else {
+                continue;
+            };
+
+            let ts_rounded = bucket.start_of(snapshot.timestamp.timestamp_millis());
+            if current.as_ref().is_some_and(|(ts, _, _)| *ts != ts_rounded) {
+                let (ts, wallets, backfilled) = current.take().unwrap();
+                let chunk = format!("{}{}", if first { "" } else { "," }, bucket_entry(ts, wallets, backfilled, agg));
+                first = false;
+                if tx.send(Ok(chunk)).await.is_err() {
+                    return;
+                }
+            }
+
+            let entry = current.get_or_insert_with(|| (ts_rounded, std::collections::HashMap::new(), false));
+            entry.1.entry(snapshot.proxy_address).or_default().add(snapshot.usdc_balance);
+            entry.2 |= snapshot.backfilled;
+        }
+
+        let mut tail = String::new();
+        if let Some((ts, wallets, backfilled)) = current {
+            tail.push_str(if first { "" } else { "," });
+            tail.push_str(&bucket_entry(ts, wallets, backfilled, agg).to_string());
+        }
+        tail.push_str("]}");
+        let _ = tx.send(Ok(tail)).await;
+    });
+
+    Ok(axum::response::Response::builder()
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(axum::body::Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallets(count: usize) -> Vec<WalletConfig> {
+        (0..count)
+            .map(|i| WalletConfig { wallet_id: format!("w{}", i), ..Default::default() })
+            .collect()
+    }
+
+    // 每个钱包模拟 50 毫秒的获取延迟，w3 获取失败
+    async fn timed_refresh(concurrency: usize) -> (Vec<String>, std::time::Duration) {
+        let started = std::time::Instant::now();
+        let results = for_each_wallet(wallets(6), concurrency, |wallet| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            (wallet.wallet_id != "w3").then_some(wallet.wallet_id)
+        }).await;
+        (results, started.elapsed())
+    }
+
+    #[test]
+    fn wallet_refreshes_share_the_cooldown_per_address() {
+        let mut started = std::collections::HashMap::new();
+        let cooldown = std::time::Duration::from_secs(10);
+
+        assert_eq!(claim_wallet_refresh(&mut started, "0xa", cooldown), None);
+        assert_eq!(claim_wallet_refresh(&mut started, "0xa", cooldown), Some(10));
+        // 其他地址不受影响
+        assert_eq!(claim_wallet_refresh(&mut started, "0xb", cooldown), None);
+
+        // 冷却期已过
+        started.insert("0xa".to_string(), std::time::Instant::now() - cooldown);
+        assert_eq!(claim_wallet_refresh(&mut started, "0xa", cooldown), None);
+        // 关闭冷却时不限制
+        assert_eq!(claim_wallet_refresh(&mut started, "0xc", std::time::Duration::ZERO), None);
+        assert_eq!(claim_wallet_refresh(&mut started, "0xc", std::time::Duration::ZERO), None);
+    }
+
+    #[tokio::test]
+    async fn wallets_are_fetched_concurrently_in_order() {
+        let (serial, serial_elapsed) = timed_refresh(1).await;
+        let (concurrent, concurrent_elapsed) = timed_refresh(3).await;
+
+        assert_eq!(concurrent, vec!["w0", "w1", "w2", "w4", "w5"]);
+        assert_eq!(concurrent, serial);
+        assert!(serial_elapsed >= std::time::Duration::from_millis(300), "{:?}", serial_elapsed);
+        assert!(concurrent_elapsed < serial_elapsed / 2, "{:?} vs {:?}", concurrent_elapsed, serial_elapsed);
     }
 }
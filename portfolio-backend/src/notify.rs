@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+use sqlx::MySqlPool;
+
+use crate::config::Settings;
+use crate::db;
+use crate::portfolio::PortfolioData;
+
+// 通过 WEBHOOK_URL 以 JSON POST 发送告警，未配置时不做任何事
+pub struct Notifier {
+    http_client: reqwest::Client,
+    url: Option<String>,
+    // 每个告警 key 最近一次发送的时间，用于冷却
+    last_sent: Mutex<HashMap<String, Instant>>,
+}
+
+impl Notifier {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            http_client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap(),
+            url: settings.webhook_url.clone(),
+            last_sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 距离该 key 上次告警已超过 cooldown 时记下本次并返回 true
+    pub fn debounce(&self, key: &str, cooldown: Duration) -> bool {
+        let mut last_sent = self.last_sent.lock().unwrap();
+        let now = Instant::now();
+        if last_sent.get(key).is_some_and(|t| now.duration_since(*t) < cooldown) {
+            return false;
+        }
+        last_sent.insert(key.to_string(), now);
+        true
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    // 请求体为 {"event": ..., "timestamp": ..., "message": ..., ...details}；发送失败只记录日志
+    pub async fn send(&self, event: &str, message: &str, details: serde_json::Value) {
+        let Some(url) = self.url.as_deref() else {
+            return;
+        };
+
+        let mut body = serde_json::json!({
+            "event": event,
+            "timestamp": chrono::Utc::now().timestamp_millis(),
+            "message": message,
+        });
+        if let (Some(body), serde_json::Value::Object(details)) = (body.as_object_mut(), details) {
+            body.extend(details);
+        }
+
+        match self.http_client.post(url).json(&body).send().await {
+            Ok(resp) if resp.status().is_success() => tracing::info!("已发送告警 {}", event),
+            Ok(resp) => tracing::warn!("发送告警 {} 失败: webhook 返回状态码 {}", event, resp.status()),
+            Err(e) => tracing::warn!("发送告警 {} 失败: {}", event, e),
+        }
+    }
+}
+
+// 对比刷新前后的总价值，|new - old| / old 超过 ALERT_THRESHOLD_PCT 的钱包写入 alerts 表并发送 portfolio_value_change 告警；
+// 冷却只限制 webhook，每次超过阈值都会写入。旧值为 0、模拟钱包和部分数据获取失败的结果不检查
+pub async fn alert_value_changes(
+    notifier: &Notifier,
+    settings: &Settings,
+    pool: &MySqlPool,
+    previous: &HashMap<String, PortfolioData>,
+    results: &[PortfolioData],
+) {
+    let threshold = settings.alert_threshold_pct;
+    if threshold.is_zero() {
+        return;
+    }
+    let cooldown = Duration::from_secs(settings.alert_cooldown_secs);
+
+    for data in results.iter().filter(|d| !d.paper && !d.partial) {
+        let Some(old) = previous.get(&data.proxy_address).map(|p| p.portfolio_total) else {
+            continue;
+        };
+        if old.is_zero() {
+            continue;
+        }
+        let delta = data.portfolio_total - old;
+        let delta_pct = (delta / old * Decimal::ONE_HUNDRED).round_dp(4);
+        if delta_pct.abs() <= threshold {
+            continue;
+        }
+        let alert = db::Alert {
+            timestamp: chrono::Utc::now(),
+            proxy_address: data.proxy_address.clone(),
+            old_value: old,
+            new_value: data.portfolio_total,
+            pct_change: delta_pct,
+        };
+        if let Err(e) = db::save_alert(pool, &alert).await {
+            tracing::warn!(proxy_address = %data.proxy_address, error = %e, "保存告警记录失败");
+        }
+        if !notifier.debounce(&format!("value_change:{}", data.proxy_address), cooldown) {
+            tracing::debug!(proxy_address = %data.proxy_address, %delta_pct, "价值变化告警处于冷却期，跳过");
+            continue;
+        }
+        tracing::warn!(proxy_address = %data.proxy_address, old_value = %old, new_value = %data.portfolio_total, %delta_pct, "钱包价值变化超过阈值");
+        notifier.send(
+            "portfolio_value_change",
+            &format!("钱包 {} 价值变化 {}%", data.proxy_address, delta_pct),
+            serde_json::json!({
+                "wallet": data.proxy_address,
+                "old_value": old,
+                "new_value": data.portfolio_total,
+                "delta": delta,
+                "delta_pct": delta_pct,
+                "threshold_pct": threshold
+            }),
+        ).await;
+    }
+}
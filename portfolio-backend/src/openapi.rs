@@ -0,0 +1,85 @@
+use axum::response::Html;
+use axum::Json;
+use utoipa::OpenApi;
+
+use crate::{analytics, live};
+
+// 由各接口上的 #[utoipa::path] 生成 OpenAPI 文档；新增接口时需要同时加到 paths 中
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Portfolio Checker API"),
+    paths(
+        crate::health,
+        crate::health_ready,
+        crate::get_wallets,
+        crate::refresh_portfolio,
+        crate::refresh_wallet,
+        crate::get_cached,
+        crate::get_wallet_portfolio,
+        crate::get_history,
+        crate::get_history_csv,
+        crate::get_alerts,
+        crate::get_dashboard,
+        crate::get_positions,
+        crate::get_positions_by_market,
+        crate::get_approvals,
+        crate::get_approval_gas,
+        live::ws,
+        live::sse,
+        analytics::summary,
+        analytics::compare,
+        analytics::timeseries,
+        analytics::twr,
+        analytics::drawdown,
+        analytics::beta,
+    )
+)]
+struct ApiDoc;
+
+pub async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Swagger UI 从 CDN 加载，读取同源的 /api/openapi.json
+pub async fn docs() -> Html<&'static str> {
+    Html(r#"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Portfolio Checker API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: '/api/openapi.json', dom_id: '#swagger-ui' });</script>
+</body>
+</html>"#)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_lists_every_api_route() {
+        let spec = ApiDoc::openapi();
+        for path in [
+            "/api/health",
+            "/api/health/ready",
+            "/api/dashboard",
+            "/api/portfolio/history.csv",
+            "/api/portfolio/positions/{address}",
+            "/api/positions/by-market/{condition_id}",
+            "/api/portfolio/approvals",
+            "/api/portfolio/approvals/gas",
+            "/api/portfolio/ws",
+            "/api/portfolio/stream",
+        ] {
+            assert!(spec.paths.paths.contains_key(path), "{} 缺少文档", path);
+        }
+        let schemas = spec.components.expect("components").schemas;
+        assert!(schemas.contains_key("HealthResponse"));
+        assert!(schemas.contains_key("PositionsResponse"));
+    }
+}
@@ -1,113 +1,1847 @@
-use alloy::primitives::Address;
-use alloy::providers::ProviderBuilder;
+use alloy::primitives::{Address, U256};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
 use alloy::sol;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use crate::config::WalletConfig;
 use crate::error::AppError;
+use crate::format::DisplayValues;
+use crate::telemetry;
 
-const POLYGON_RPC: &str = "https://polygon-rpc.com";
-const USDC_ADDRESS: &str = "0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174";
-const DATA_API_URL: &str = "https://data-api.polymarket.com";
+const USDC_DECIMALS: u8 = 6;
+pub const DATA_API_URL: &str = "https://data-api.polymarket.com";
+const CLOB_API_URL: &str = "https://clob.polymarket.com";
+// data-api /positions 按 limit/offset 分页，每页最多 500 条；页数设上限，避免接口异常时无限翻页
+const POSITIONS_PAGE_SIZE: usize = 500;
+const MAX_POSITIONS_PAGES: usize = 20;
+
+// Multicall3 在 Polygon 和 Amoy 上部署在同一地址
+const MULTICALL3_ADDRESS: Address = alloy::primitives::address!("0xcA11bde05977b3631167028862bE2a173976CA11");
+
+// 核对链上 CTF 余额时允许的数量误差（份额）
+const CTF_SIZE_TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
+
+// 某条链上的合约地址，spenders 为交易前需要授权 USDC 和 CTF 的合约
+pub struct ChainContracts {
+    pub usdc: &'static str,
+    pub conditional_tokens: &'static str,
+    pub exchange: &'static str,
+    pub spenders: &'static [(&'static str, &'static str)],
+}
+
+// 与 rs-clob-client 中 contract_config(POLYGON, ..) 保持一致
+const POLYGON_CONTRACTS: ChainContracts = ChainContracts {
+    usdc: "0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174",
+    conditional_tokens: "0x4D97DCd97eC945f40cF65F87097ACe5EA0476045",
+    exchange: "0x4bFb41d5B3570DeFd03C39a9A4D8dE6Bd8B8982E",
+    spenders: &[
+        ("exchange", "0x4bFb41d5B3570DeFd03C39a9A4D8dE6Bd8B8982E"),
+        ("neg_risk_exchange", "0xC5d563A36AE78145C45a50134d48A1215220f80a"),
+        ("neg_risk_adapter", "0xd91E80cF2E7be2e162c6513ceD06f1dD0dA35296"),
+    ],
+};
+
+// 与 rs-clob-client 中 contract_config(AMOY, ..) 保持一致；rs-clob-client 没有列出 Amoy 上的 neg risk adapter
+const AMOY_CONTRACTS: ChainContracts = ChainContracts {
+    usdc: "0x9c4e1703476e875070ee25b56a58b008cfb8fa78",
+    conditional_tokens: "0x69308FB512518e39F9b16112fA8d994F4e2Bf8bB",
+    exchange: "0xdFE02Eb6733538f8Ea35D585af8DE5958AD99E40",
+    spenders: &[
+        ("exchange", "0xdFE02Eb6733538f8Ea35D585af8DE5958AD99E40"),
+        ("neg_risk_exchange", "0xd91E80cF2E7be2e162c6513ceD06f1dD0dA35296"),
+    ],
+};
+
+// CHAIN 选择的链，决定默认 RPC 节点和合约地址；data-api 只有主网数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chain {
+    #[default]
+    Polygon,
+    Amoy,
+}
+
+impl Chain {
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        match value.trim().to_lowercase().as_str() {
+            "polygon" => Ok(Chain::Polygon),
+            "amoy" => Ok(Chain::Amoy),
+            other => Err(AppError::ParseError(format!("未知的 CHAIN={}，只支持 polygon 和 amoy", other))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chain::Polygon => "polygon",
+            Chain::Amoy => "amoy",
+        }
+    }
+
+    // 未设置 POLYGON_RPC_URL(S) 时使用的公共节点，限流比较严格
+    pub fn default_rpc(self) -> &'static str {
+        match self {
+            Chain::Polygon => "https://polygon-rpc.com",
+            Chain::Amoy => "https://rpc-amoy.polygon.technology",
+        }
+    }
+
+    pub fn contracts(self) -> &'static ChainContracts {
+        match self {
+            Chain::Polygon => &POLYGON_CONTRACTS,
+            Chain::Amoy => &AMOY_CONTRACTS,
+        }
+    }
+}
 
 sol! {
     #[sol(rpc)]
     interface IERC20 {
         function balanceOf(address owner) external view returns (uint256);
+        function allowance(address owner, address spender) external view returns (uint256);
+        function approve(address spender, uint256 amount) external returns (bool);
+        function decimals() external view returns (uint8);
+    }
+
+    #[sol(rpc)]
+    interface IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+        struct Result3 {
+            bool success;
+            bytes returnData;
+        }
+        function aggregate3(Call3[] calls) external payable returns (Result3[] returnData);
+    }
+
+    #[sol(rpc)]
+    interface IERC1155 {
+        function isApprovedForAll(address account, address operator) external view returns (bool);
+        function setApprovalForAll(address operator, bool approved) external;
+        function balanceOfBatch(address[] accounts, uint256[] ids) external view returns (uint256[]);
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct PortfolioData {
     pub proxy_address: String,
-    pub usdc_balance: f64,
-    pub positions_value: f64,
-    pub portfolio_total: f64,
+    // 代理钱包和签名 EOA（若配置）的 USDC 合计
+    pub usdc_balance: Decimal,
+    // 其中来自签名 EOA 的部分，未配置 signer_address 时为 None
+    #[serde(default)]
+    pub signer_usdc_balance: Option<Decimal>,
+    pub positions_value: Decimal,
+    // 配置了 EXCLUDED_MARKETS 时，排除前的持仓价值；positions_value 为排除后的值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unfiltered_positions_value: Option<Decimal>,
+    // 持有的不同市场数量，只有拿到持仓明细时才有值
+    #[serde(default)]
+    pub positions_count: Option<usize>,
+    // 逐个持仓的明细，只在 POSITIONS_DETAIL 开启时填充；positions_value 为其中未排除持仓的合计
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positions: Option<Vec<Position>>,
+    // 配置了 EXTRA_TOKENS 时各代币的余额（按 symbol），包含主代币 USDC；额外代币只统计代理钱包，不计入 portfolio_total
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub token_balances: BTreeMap<String, Decimal>,
+    // 按订单簿深度估算的可变现持仓价值，仅在 DEPTH_VALUATION 开启时计算
+    #[serde(default)]
+    pub liquidation_value: Option<Decimal>,
+    pub portfolio_total: Decimal,
     pub last_updated: i64,
+    // 模拟钱包的数据来自手动设置的余额
+    #[serde(default)]
+    pub paper: bool,
+    // 钱包已暂停刷新，数据停留在暂停前最后一次刷新的结果，只在返回响应时填充
+    #[serde(default)]
+    pub paused: bool,
+    // 缓存数据距上次刷新的秒数及是否超过 CACHE_TTL_SECS，只在从缓存返回响应时填充
+    #[serde(default)]
+    pub stale: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_seconds: Option<i64>,
+    // 最近 INACTIVE_LOOKBACK_SNAPSHOTS 条快照的总价值都为 0，只在从缓存返回响应时填充
+    #[serde(default)]
+    pub inactive: bool,
+    // 总价值低于 MIN_PORTFOLIO_VALUE，只在从缓存返回响应时填充
+    #[serde(default)]
+    pub dust: bool,
+    // 本次刷新没有重新获取、沿用上一次数据的部分（usdc / signer_usdc / positions）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub carried_forward: Vec<String>,
+    // USDC 余额（含签名地址）和持仓价值中有一项获取失败，或未请求的一项没有上一次的数据可沿用，
+    // 该项沿用上一次的数据（没有时为 0）；这样的数据不写入数据库
+    #[serde(default)]
+    pub partial: bool,
+    // 按钱包展示货币格式化的金额，只在返回响应时填充
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<DisplayValues>,
+}
+
+// data-api /positions 返回的单个持仓
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Position {
+    #[serde(default)]
+    pub asset: String,
+    #[serde(default, alias = "conditionId")]
+    pub condition_id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub outcome: String,
+    #[serde(default, deserialize_with = "deserialize_number")]
+    pub size: Decimal,
+    #[serde(default, alias = "avgPrice", deserialize_with = "deserialize_number")]
+    pub avg_price: Decimal,
+    #[serde(default, alias = "curPrice", deserialize_with = "deserialize_number")]
+    pub cur_price: Decimal,
+    #[serde(default, alias = "currentValue", deserialize_with = "deserialize_number")]
+    pub current_value: Decimal,
+}
+
+// 需要查询余额的 ERC20 代币
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub symbol: String,
+    pub address: Address,
+    pub decimals: u8,
+}
+
+impl Token {
+    pub fn usdc(chain: Chain) -> Self {
+        Self {
+            symbol: "USDC".to_string(),
+            address: chain.contracts().usdc.parse().unwrap(),
+            decimals: USDC_DECIMALS,
+        }
+    }
+}
+
+// 单个地址缓存的 /value 结果及其获取时间
+type CachedValue = tokio::sync::Mutex<Option<(std::time::Instant, Decimal)>>;
+
+// 刷新前用 Multicall3 批量查到的 USDC 余额，fetch_portfolio 优先使用，没有的地址再单独查询
+pub type UsdcBalances = HashMap<Address, Decimal>;
+
+// 解析 EXTRA_TOKENS，格式如 USDT:0xc2132D05D31c914a87C6611C10748AEb04B58e8F:6，多个代币用逗号分隔
+pub fn parse_tokens(value: Option<&str>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for entry in value.unwrap_or("").split(',').filter(|e| !e.trim().is_empty()) {
+        let parts: Vec<&str> = entry.split(':').map(str::trim).collect();
+        match parts.as_slice() {
+            [symbol, address, decimals] if !symbol.is_empty() => {
+                match (address.parse::<Address>(), decimals.parse::<u8>()) {
+                    (Ok(address), Ok(decimals)) => tokens.push(Token { symbol: symbol.to_uppercase(), address, decimals }),
+                    _ => tracing::warn!("无法解析代币配置: {}", entry),
+                }
+            }
+            _ => tracing::warn!("无法解析代币配置: {}", entry),
+        }
+    }
+    tokens
+}
+
+// 持仓价值的各项结果：没有配置排除市场时 unfiltered 为 None，
+// 走聚合接口且未开启持仓计数时 count 为 None，未开启持仓明细时 positions 为 None
+#[derive(Debug, Default)]
+struct PositionsValues {
+    value: Decimal,
+    unfiltered: Option<Decimal>,
+    count: Option<usize>,
+    positions: Option<Vec<Position>>,
+}
+
+// CLOB /book 返回的订单簿，价格和数量都是字符串
+#[derive(Debug, Deserialize)]
+struct OrderBook {
+    #[serde(default)]
+    bids: Vec<BookLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BookLevel {
+    price: String,
+    size: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalStatus {
+    pub spender: String,
+    pub spender_address: String,
+    pub usdc_allowance: f64,
+    pub usdc_approved: bool,
+    pub ctf_approved: bool,
+}
+
+// 持仓列表的排序方式，None 保持 data-api 返回的原始顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionSort {
+    ValueDesc,
+    ValueAsc,
+    SharesDesc,
+}
+
+impl PositionSort {
+    pub fn parse(value: Option<&str>) -> Result<Option<PositionSort>, AppError> {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            None | Some("") => Ok(None),
+            Some("value_desc") => Ok(Some(PositionSort::ValueDesc)),
+            Some("value_asc") => Ok(Some(PositionSort::ValueAsc)),
+            Some("shares_desc") => Ok(Some(PositionSort::SharesDesc)),
+            Some(other) => Err(AppError::ParseError(format!("未知的排序方式: {}", other))),
+        }
+    }
+
+    // 稳定排序，值相同的持仓保持原始顺序
+    pub fn apply(self, positions: &mut [Position]) {
+        match self {
+            PositionSort::ValueDesc => positions.sort_by_key(|p| std::cmp::Reverse(p.current_value)),
+            PositionSort::ValueAsc => positions.sort_by_key(|p| p.current_value),
+            PositionSort::SharesDesc => positions.sort_by_key(|p| std::cmp::Reverse(p.size)),
+        }
+    }
+}
+
+// 刷新时要获取的部分，未选中的部分沿用上一次的数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Components {
+    pub usdc: bool,
+    pub positions: bool,
+}
+
+impl Components {
+    pub const ALL: Components = Components { usdc: true, positions: true };
+
+    // 逗号分隔的 usdc/positions，未给出时获取全部
+    pub fn parse(value: Option<&str>) -> Result<Components, AppError> {
+        let Some(value) = value.filter(|v| !v.trim().is_empty()) else {
+            return Ok(Components::ALL);
+        };
+        let mut components = Components { usdc: false, positions: false };
+        for part in value.split(',').map(|p| p.trim().to_lowercase()) {
+            match part.as_str() {
+                "usdc" => components.usdc = true,
+                "positions" => components.positions = true,
+                "" => {}
+                other => return Err(AppError::ParseError(format!("未知的刷新部分: {}", other))),
+            }
+        }
+        Ok(components)
+    }
+}
+
+// data-api 持仓与链上 CTF 代币余额不一致的条目
+#[derive(Debug, Clone, Serialize)]
+pub struct CtfDiscrepancy {
+    pub asset: String,
+    pub title: String,
+    pub api_size: Decimal,
+    pub onchain_size: Decimal,
+    // ghost：接口报告了持仓但链上没有代币；size_mismatch：数量不一致；unreported：接口显示已清仓但链上仍有余额
+    pub kind: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CtfReport {
+    pub proxy_address: String,
+    pub checked_at: i64,
+    pub checked: usize,
+    pub discrepancies: Vec<CtfDiscrepancy>,
+}
+
+// 单笔授权交易的 gas 估算，只做 eth_estimateGas，不会发送交易
+#[derive(Debug, Clone, Serialize)]
+pub struct GasEstimate {
+    pub spender: String,
+    pub spender_address: String,
+    // usdc_approve 或 ctf_set_approval_for_all
+    pub action: &'static str,
+    pub gas: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 pub struct PortfolioService {
     http_client: reqwest::Client,
+    chain: Chain,
+    // 之后通过 with_rpc_urls 创建 provider 时沿用同一份配置
+    http_config: HttpConfig,
+    // 按顺序排列的 RPC 节点，所有链上调用共用这些 provider 及其 HTTP 连接
+    rpc_endpoints: Vec<(reqwest::Url, DynProvider)>,
+    // 最近一次调用成功的节点，后续调用优先使用
+    preferred_rpc: AtomicUsize,
+    depth_valuation: bool,
+    value_fields: Vec<String>,
+    // 不计入持仓价值的市场 condition id（小写）
+    excluded_markets: Vec<String>,
+    count_positions: bool,
+    include_positions: bool,
+    // 主代币的余额填入 usdc_balance，额外代币只记录在 token_balances 中
+    usdc: Token,
+    extra_tokens: Vec<Token>,
+    // 链上 decimals() 查到的小数位数，key 为代币合约地址；每个代币只查询一次
+    token_decimals: std::sync::RwLock<HashMap<Address, u8>>,
+    // USDC 余额和持仓价值请求的最大尝试次数及首次重试前的等待时间
+    retry_attempts: u32,
+    retry_base_delay: std::time::Duration,
+    // data-api /value 返回非 JSON（如故障时的 HTML 错误页）时按 0 处理，默认返回错误
+    non_json_as_zero: bool,
+    // /value 结果的短期缓存，key 为小写代理地址；每个地址一把锁，同一地址的并发请求只有第一个访问 data-api
+    positions_value_ttl: std::time::Duration,
+    data_api_url: String,
+    positions_value_cache: std::sync::Mutex<HashMap<String, Arc<CachedValue>>>,
+}
+
+// data-api、CLOB 和 RPC 共用的 HTTP 客户端配置；同一主机的空闲连接会被复用
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub timeout: std::time::Duration,
+    pub connect_timeout: std::time::Duration,
+    pub pool_idle_timeout: std::time::Duration,
+    pub pool_max_idle_per_host: usize,
 }
 
 impl PortfolioService {
-    pub fn new() -> Self {
+    pub fn with_config(config: &HttpConfig) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .connect_timeout(config.connect_timeout)
+            .pool_idle_timeout(config.pool_idle_timeout)
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .build()
+            .unwrap();
         Self {
-            http_client: reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(10))
-                .build()
-                .unwrap(),
+            rpc_endpoints: vec![rpc_endpoint(config, Chain::default().default_rpc().parse().unwrap())],
+            http_client,
+            http_config: config.clone(),
+            preferred_rpc: AtomicUsize::new(0),
+            depth_valuation: false,
+            value_fields: vec!["value".to_string()],
+            excluded_markets: Vec::new(),
+            count_positions: false,
+            include_positions: false,
+            non_json_as_zero: false,
+            positions_value_ttl: std::time::Duration::ZERO,
+            data_api_url: DATA_API_URL.to_string(),
+            positions_value_cache: std::sync::Mutex::new(HashMap::new()),
+            chain: Chain::default(),
+            usdc: Token::usdc(Chain::default()),
+            extra_tokens: Vec::new(),
+            token_decimals: std::sync::RwLock::new(HashMap::new()),
+            retry_attempts: 1,
+            retry_base_delay: std::time::Duration::from_millis(200),
         }
     }
 
-    pub async fn fetch_portfolio(&self, proxy_address: &str) -> Result<PortfolioData, AppError> {
-        let (usdc_balance, positions_value) = tokio::join!(
-            self.get_usdc_balance(proxy_address),
-            self.get_positions_value(proxy_address)
+    // 切换链时同时换成该链的 USDC 和默认 RPC 节点，之后的 with_rpc_urls 可以覆盖节点
+    pub fn with_chain(mut self, chain: Chain) -> Self {
+        self.chain = chain;
+        self.usdc = Token::usdc(chain);
+        self.rpc_endpoints = vec![rpc_endpoint(&self.http_config, chain.default_rpc().parse().unwrap())];
+        self.preferred_rpc = AtomicUsize::new(0);
+        self
+    }
+
+    // 连接失败时按顺序尝试下一个节点，空列表时保留默认节点
+    pub fn with_rpc_urls(mut self, urls: Vec<reqwest::Url>) -> Self {
+        if !urls.is_empty() {
+            self.rpc_endpoints = urls.into_iter().map(|url| rpc_endpoint(&self.http_config, url)).collect();
+            self.preferred_rpc = AtomicUsize::new(0);
+        }
+        self
+    }
+
+    // 主代币和所有额外代币
+    pub fn tokens(&self) -> impl Iterator<Item = &Token> {
+        std::iter::once(&self.usdc).chain(&self.extra_tokens)
+    }
+
+    // 诊断用：各节点的主机名（不含路径，避免泄露 URL 中的 API key）以及当前优先使用的节点
+    pub fn rpc_status(&self) -> serde_json::Value {
+        let hosts: Vec<_> = self.rpc_endpoints.iter().map(|(url, _)| url.host_str().unwrap_or("").to_string()).collect();
+        let preferred = self.preferred_rpc.load(Ordering::Relaxed);
+        serde_json::json!({
+            "endpoints": hosts,
+            "preferred": hosts.get(preferred)
+        })
+    }
+
+    fn current_provider(&self) -> &DynProvider {
+        let index = self.preferred_rpc.load(Ordering::Relaxed) % self.rpc_endpoints.len();
+        &self.rpc_endpoints[index].1
+    }
+
+    // 从当前优先的节点开始调用，只有连接类错误（RpcConnectError）才切换到下一个节点；
+    // 合约 revert 等调用本身的错误换节点也不会成功，直接返回。成功的节点成为新的优先节点
+    async fn on_rpc<T, F, Fut>(&self, mut call: F) -> Result<T, AppError>
+    where
+        F: FnMut(DynProvider) -> Fut,
+        Fut: std::future::Future<Output = Result<T, AppError>>,
+    {
+        let count = self.rpc_endpoints.len();
+        let start = self.preferred_rpc.load(Ordering::Relaxed);
+        let mut attempt = 0;
+        loop {
+            let index = (start + attempt) % count;
+            let (url, provider) = &self.rpc_endpoints[index];
+            match call(provider.clone()).await {
+                Ok(value) => {
+                    if attempt > 0 {
+                        self.preferred_rpc.store(index, Ordering::Relaxed);
+                        tracing::info!("已切换到 RPC 节点 {}", url.host_str().unwrap_or(""));
+                    }
+                    return Ok(value);
+                }
+                Err(e @ AppError::RpcConnectError(_)) if attempt + 1 < count => {
+                    tracing::warn!("RPC 节点 {} 不可用，尝试下一个: {}", url.host_str().unwrap_or(""), e);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // 选择 data-api 响应中哪些字段计入持仓价值，多个字段会相加
+    pub fn with_value_fields(mut self, fields: Vec<String>) -> Self {
+        if !fields.is_empty() {
+            self.value_fields = fields;
+        }
+        self
+    }
+
+    // 排除市场需要逐个持仓的明细，设置后持仓价值改为从 /positions 汇总，而不是使用 /value 的聚合值
+    pub fn with_excluded_markets(mut self, markets: Vec<String>) -> Self {
+        self.excluded_markets = markets.into_iter().map(|m| m.to_lowercase()).collect();
+        self
+    }
+
+    // /value 只返回聚合值，开启后在聚合路径下额外请求一次 /positions 统计持仓数量
+    pub fn with_positions_count(mut self, enabled: bool) -> Self {
+        self.count_positions = enabled;
+        self
+    }
+
+    // 开启后持仓价值从 /positions 明细汇总，并把明细放进 PortfolioData.positions
+    pub fn with_positions_detail(mut self, enabled: bool) -> Self {
+        self.include_positions = enabled;
+        self
+    }
+
+    pub fn with_non_json_as_zero(mut self, enabled: bool) -> Self {
+        self.non_json_as_zero = enabled;
+        self
+    }
+
+    pub fn with_positions_value_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.positions_value_ttl = ttl;
+        self
+    }
+
+    // 改用 data-api 的镜像或代理地址，末尾的 / 会去掉
+    pub fn with_data_api_url(mut self, url: String) -> Self {
+        self.data_api_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_extra_tokens(mut self, tokens: Vec<Token>) -> Self {
+        self.extra_tokens = tokens.into_iter().filter(|t| t.address != self.usdc.address).collect();
+        self
+    }
+
+    // 公共 RPC 和 data-api 经常出现短暂的网络错误，失败后按指数退避加随机抖动重试
+    pub fn with_retry(mut self, attempts: u32, base_delay_ms: u64) -> Self {
+        self.retry_attempts = attempts.max(1);
+        self.retry_base_delay = std::time::Duration::from_millis(base_delay_ms);
+        self
+    }
+
+    // 开启后每次刷新会为每个持仓额外请求一次订单簿
+    pub fn with_depth_valuation(mut self, enabled: bool) -> Self {
+        self.depth_valuation = enabled;
+        self
+    }
+
+    // 只获取选中的部分，其余字段沿用 previous（上一次已知的数据），并记录在 carried_forward 中。
+    // 选中的部分全部获取失败时返回错误；只有一部分失败时该部分同样沿用 previous，并标记 partial
+    pub async fn fetch_portfolio(
+        &self,
+        wallet: &WalletConfig,
+        components: Components,
+        previous: Option<&PortfolioData>,
+        prefetched: &UsdcBalances,
+    ) -> Result<PortfolioData, AppError> {
+        if wallet.paper {
+            return Ok(paper_portfolio(wallet));
+        }
+
+        let started = std::time::Instant::now();
+        let proxy_address = wallet.proxy_address.as_str();
+        let (usdc, positions) = tokio::join!(
+            async {
+                if !components.usdc {
+                    return None;
+                }
+                let (usdc_balance, signer_usdc_balance, token_balances) = tokio::join!(
+                    self.usdc_balance_prefetched(prefetched, proxy_address),
+                    self.get_signer_usdc_balance(wallet, prefetched),
+                    self.get_extra_token_balances(proxy_address)
+                );
+                Some(usdc_balance.map(|balance| (balance, signer_usdc_balance, token_balances)))
+            },
+            async {
+                if !components.positions {
+                    return None;
+                }
+                let values = match self.get_positions_values(proxy_address).await {
+                    Ok(values) => values,
+                    Err(e) => return Some(Err(e)),
+                };
+                let liquidation_value = if self.depth_valuation {
+                    match self.get_liquidation_value(proxy_address).await {
+                        Ok(value) => Some(value),
+                        Err(e) => {
+                            tracing::warn!(proxy_address, wallet_name = %wallet.name, error = %e, "估算可变现价值失败");
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                Some(Ok((values, liquidation_value)))
+            }
         );
 
-        let usdc_balance = usdc_balance.unwrap_or(0.0);
-        let positions_value = positions_value.unwrap_or(0.0);
+        // 所有选中的部分都失败时不构造数据，避免把故障期间的 0 当成真实余额保存
+        let (usdc, positions) = match (usdc, positions) {
+            (Some(Err(usdc_err)), Some(Err(positions_err))) => {
+                return Err(AppError::ApiError(format!("USDC 余额和持仓价值均获取失败: {}; {}", usdc_err, positions_err), None));
+            }
+            (Some(Err(e)), None) | (None, Some(Err(e))) => return Err(e),
+            (usdc, positions) => (usdc, positions),
+        };
+        let mut partial = false;
+        let mut carried_forward = Vec::new();
+        let usdc = match usdc {
+            Some(Err(e)) => {
+                tracing::warn!(proxy_address, wallet_name = %wallet.name, error = %e, "获取 USDC 余额失败，沿用上一次的值");
+                partial = true;
+                None
+            }
+            // 签名地址的余额获取失败时同样沿用上一次的值并标记 partial，否则合计会少算这部分，
+            // 保存下来还可能触发一次虚假的价值下跌告警
+            Some(Ok((balance, signer, token_balances))) => {
+                let signer = match signer {
+                    Some(Err(e)) => {
+                        tracing::warn!(proxy_address, wallet_name = %wallet.name, error = %e, "获取签名地址 USDC 余额失败，沿用上一次的值");
+                        partial = true;
+                        carried_forward.push("signer_usdc".to_string());
+                        previous.and_then(|p| p.signer_usdc_balance)
+                    }
+                    Some(Ok(value)) => Some(value),
+                    None => None,
+                };
+                Some((balance + signer.unwrap_or_default(), signer, token_balances))
+            }
+            None => None,
+        };
+        let positions = match positions {
+            // data-api 没有该地址的记录，多半是地址填错或代理钱包尚未使用过，单独提示
+            Some(Err(e)) if e.upstream_status() == Some(404) => {
+                tracing::warn!(proxy_address, wallet_name = %wallet.name, "data-api 返回 404，没有该地址的持仓记录，本次不保存快照");
+                partial = true;
+                None
+            }
+            Some(Err(e)) => {
+                tracing::warn!(proxy_address, wallet_name = %wallet.name, error = %e, "获取持仓价值失败，沿用上一次的值");
+                partial = true;
+                None
+            }
+            Some(Ok(value)) => Some(value),
+            None => None,
+        };
+
+        // 沿用的部分没有上一次的值可用时（冷启动或新加的钱包）只能填 0，同样标记 partial，避免 0 被当成真实余额写入历史
+        if previous.is_none() && (usdc.is_none() || positions.is_none()) {
+            partial = true;
+        }
+        let (usdc_balance, signer_usdc_balance, mut token_balances) = usdc.unwrap_or_else(|| {
+            carried_forward.push("usdc".to_string());
+            previous.map_or((Decimal::ZERO, None, BTreeMap::new()), |p| {
+                (p.usdc_balance, p.signer_usdc_balance, p.token_balances.clone())
+            })
+        });
+        if !token_balances.is_empty() {
+            token_balances.insert(self.usdc.symbol.clone(), usdc_balance);
+        }
+        telemetry::record_fetch_portfolio(started);
+        let (values, liquidation_value) = positions.unwrap_or_else(|| {
+            carried_forward.push("positions".to_string());
+            previous.map_or((PositionsValues::default(), None), |p| {
+                let values = PositionsValues {
+                    value: p.positions_value,
+                    unfiltered: p.unfiltered_positions_value,
+                    count: p.positions_count,
+                    positions: p.positions.clone(),
+                };
+                (values, p.liquidation_value)
+            })
+        });
+
+        let portfolio_total = usdc_balance + values.value;
+        tracing::info!(
+            proxy_address,
+            wallet_name = %wallet.name,
+            portfolio_total = %portfolio_total,
+            latency_ms = started.elapsed().as_millis() as u64,
+            "获取钱包数据完成"
+        );
 
         Ok(PortfolioData {
             proxy_address: proxy_address.to_string(),
             usdc_balance,
-            positions_value,
-            portfolio_total: usdc_balance + positions_value,
+            signer_usdc_balance,
+            positions_value: values.value,
+            unfiltered_positions_value: values.unfiltered,
+            positions_count: values.count,
+            positions: values.positions,
+            token_balances,
+            liquidation_value,
+            portfolio_total,
             last_updated: chrono::Utc::now().timestamp_millis(),
+            paper: false,
+            paused: false,
+            stale: false,
+            age_seconds: None,
+            inactive: false,
+            dust: false,
+            carried_forward,
+            partial,
+            display: None,
         })
     }
 
+    // 就绪检查用：最轻量的 eth_blockNumber 调用，失败时同样切换节点
+    pub async fn block_number(&self) -> Result<u64, AppError> {
+        self.on_rpc(|provider| async move {
+            provider
+                .get_block_number()
+                .await
+                .map_err(|e| AppError::RpcConnectError(format!("{}", e)))
+        }).await
+    }
 
-    async fn get_usdc_balance(&self, proxy_address: &str) -> Result<f64, AppError> {
-        let provider = ProviderBuilder::new()
-            .connect_http(POLYGON_RPC.parse().unwrap());
+    // 自检用：对已知地址做一次真实的 balanceOf 调用
+    pub async fn check_rpc(&self) -> Result<Decimal, AppError> {
+        self.get_usdc_balance(self.chain.contracts().exchange).await
+    }
 
-        let usdc_addr: Address = USDC_ADDRESS.parse()
-            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
-        
-        let wallet_addr: Address = proxy_address.parse()
+    // 自检用：确认 data-api 可访问并返回成功状态
+    pub async fn check_data_api(&self) -> Result<(), AppError> {
+        let url = format!("{}/value?user={}", self.data_api_url, POLYGON_CONTRACTS.exchange);
+        let resp = self.http_client
+            .get(&url)
+            .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7)")
+            .send()
+            .await
+            .map_err(|e| AppError::ApiError(format!("{}", e), None))?;
+
+        if !resp.status().is_success() {
+            return Err(AppError::ApiError(format!("data-api 返回状态码 {}", resp.status()), Some(resp.status().as_u16())));
+        }
+        Ok(())
+    }
+
+    // 持仓只在代理钱包上，签名 EOA 只统计 USDC；未配置 signer_address 时为 None
+    async fn get_signer_usdc_balance(&self, wallet: &WalletConfig, prefetched: &UsdcBalances) -> Option<Result<Decimal, AppError>> {
+        let signer = wallet.signer_address.as_deref()?;
+        Some(self.usdc_balance_prefetched(prefetched, signer).await)
+    }
+
+
+    async fn get_usdc_balance(&self, proxy_address: &str) -> Result<Decimal, AppError> {
+        self.get_token_balance(proxy_address, &self.usdc).await
+    }
+
+    async fn usdc_balance_prefetched(&self, prefetched: &UsdcBalances, owner: &str) -> Result<Decimal, AppError> {
+        let cached = owner.parse::<Address>().ok().and_then(|address| prefetched.get(&address));
+        match cached {
+            Some(balance) => Ok(*balance),
+            None => self.get_usdc_balance(owner).await,
+        }
+    }
+
+    // 用 Multicall3 在一次 RPC 调用里查询所有地址的 USDC 余额，结果与 addresses 顺序一致；
+    // multicall 失败（如 revert 或节点不支持）时改为逐个查询
+    pub async fn get_usdc_balances_batch(&self, addresses: &[Address]) -> Result<Vec<Decimal>, AppError> {
+        if addresses.is_empty() {
+            return Ok(Vec::new());
+        }
+        match self.multicall_balances(&self.usdc, addresses).await {
+            Ok(balances) => Ok(balances),
+            Err(e) => {
+                tracing::warn!("Multicall3 批量查询 USDC 余额失败，改为逐个查询: {}", e);
+                futures::future::try_join_all(addresses.iter().map(|address| {
+                    let owner = address.to_string();
+                    async move { self.get_usdc_balance(&owner).await }
+                })).await
+            }
+        }
+    }
+
+    async fn multicall_balances(&self, token: &Token, addresses: &[Address]) -> Result<Vec<Decimal>, AppError> {
+        use alloy::sol_types::SolCall;
+
+        let decimals = self.decimals(token).await;
+
+        // allowFailure=false：任何一个子调用失败都会让整个 multicall revert，由调用方回退到逐个查询
+        let calls: Vec<IMulticall3::Call3> = addresses
+            .iter()
+            .map(|owner| IMulticall3::Call3 {
+                target: token.address,
+                allowFailure: false,
+                callData: IERC20::balanceOfCall { owner: *owner }.abi_encode().into(),
+            })
+            .collect();
+
+        let results = self.on_rpc(|provider| {
+            let calls = calls.clone();
+            async move {
+                let started = std::time::Instant::now();
+                let result = IMulticall3::new(MULTICALL3_ADDRESS, provider).aggregate3(calls).call().await;
+                telemetry::record_rpc_call("multicall_balanceOf", started, result.is_ok());
+                result.map_err(AppError::from)
+            }
+        }).await?;
+
+        if results.len() != addresses.len() {
+            return Err(AppError::RpcError(format!("Multicall3 返回 {} 条结果，预期 {} 条", results.len(), addresses.len())));
+        }
+        results
+            .iter()
+            .map(|result| {
+                let raw = IERC20::balanceOfCall::abi_decode_returns(&result.returnData)
+                    .map_err(|e| AppError::ParseError(format!("解析 balanceOf 返回值失败: {}", e)))?;
+                token_amount(raw, decimals)
+            })
+            .collect()
+    }
+
+    // 查询失败的额外代币记录警告后跳过，不影响 USDC 余额
+    async fn get_extra_token_balances(&self, owner: &str) -> BTreeMap<String, Decimal> {
+        let mut balances = BTreeMap::new();
+        for token in &self.extra_tokens {
+            match self.get_token_balance(owner, token).await {
+                Ok(balance) => {
+                    balances.insert(token.symbol.clone(), balance);
+                }
+                Err(e) => tracing::warn!("获取 {} 的 {} 余额失败: {}", owner, token.symbol, e),
+            }
+        }
+        balances
+    }
+
+    pub async fn get_token_balance(&self, owner: &str, token: &Token) -> Result<Decimal, AppError> {
+        let what = format!(" {} 余额", token.symbol);
+        self.retrying(&what, owner, || self.fetch_token_balance(owner, token)).await
+    }
+
+    async fn fetch_token_balance(&self, owner: &str, token: &Token) -> Result<Decimal, AppError> {
+        let wallet_addr: Address = owner.parse()
             .map_err(|e| AppError::ParseError(format!("{}", e)))?;
 
-        let contract = IERC20::new(usdc_addr, &provider);
+        let result = self.on_rpc(|provider| async move {
+            let started = std::time::Instant::now();
+            let result = IERC20::new(token.address, provider).balanceOf(wallet_addr).call().await;
+            telemetry::record_rpc_call("balanceOf", started, result.is_ok());
+            result.map_err(AppError::from)
+        }).await?;
+
+        token_amount(result, self.decimals(token).await)
+    }
+
+    // 代币的小数位数，首次用到时调用合约的 decimals() 并缓存；
+    // 查询失败时本次使用配置中的值（USDC 为 6，额外代币为 EXTRA_TOKENS 中填写的值），下次再查
+    async fn decimals(&self, token: &Token) -> u8 {
+        let cached = self.token_decimals.read().unwrap().get(&token.address).copied();
+        if let Some(decimals) = cached {
+            return decimals;
+        }
+
+        let address = token.address;
+        let result = self.on_rpc(|provider| async move {
+            let started = std::time::Instant::now();
+            let result = IERC20::new(address, provider).decimals().call().await;
+            telemetry::record_rpc_call("decimals", started, result.is_ok());
+            result.map_err(AppError::from)
+        }).await;
+        match result {
+            Ok(decimals) => {
+                if decimals != token.decimals {
+                    tracing::warn!("{} 链上的小数位数为 {}，与配置的 {} 不一致，以链上为准", token.symbol, decimals, token.decimals);
+                }
+                self.token_decimals.write().unwrap().insert(address, decimals);
+                decimals
+            }
+            Err(e) => {
+                tracing::warn!("查询 {} 的小数位数失败，暂时使用配置的 {}: {}", token.symbol, token.decimals, e);
+                token.decimals
+            }
+        }
+    }
+
+    // 配置了排除市场或开启了持仓明细时走 /positions 明细汇总，否则使用 /value 的聚合值
+    async fn get_positions_values(&self, proxy_address: &str) -> Result<PositionsValues, AppError> {
+        if self.excluded_markets.is_empty() && !self.include_positions {
+            let value = self.get_positions_value(proxy_address).await?;
+            let count = if self.count_positions {
+                match self.get_positions(proxy_address).await {
+                    Ok(positions) => Some(count_markets(&positions)),
+                    Err(e) => {
+                        tracing::warn!("统计 {} 持仓数量失败: {}", proxy_address, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            return Ok(PositionsValues { value, count, ..Default::default() });
+        }
+
+        let positions = self.get_positions(proxy_address).await?;
+        let unfiltered: Decimal = positions.iter().map(|p| p.current_value).sum();
+        let filtered: Decimal = positions
+            .iter()
+            .filter(|p| !self.excluded_markets.contains(&p.condition_id.to_lowercase()))
+            .map(|p| p.current_value)
+            .sum();
+        Ok(PositionsValues {
+            value: filtered,
+            unfiltered: (!self.excluded_markets.is_empty()).then_some(unfiltered),
+            count: Some(count_markets(&positions)),
+            positions: self.include_positions.then_some(positions),
+        })
+    }
+
+    // 缓存未过期时直接返回；请求失败不缓存，等待同一把锁的请求会各自再请求一次
+    async fn get_positions_value(&self, proxy_address: &str) -> Result<Decimal, AppError> {
+        if self.positions_value_ttl.is_zero() {
+            return self.retrying("持仓价值", proxy_address, || self.fetch_positions_value(proxy_address)).await;
+        }
+
+        let slot = self.positions_value_cache
+            .lock()
+            .unwrap()
+            .entry(proxy_address.to_lowercase())
+            .or_default()
+            .clone();
+        let mut entry = slot.lock().await;
+        if let Some((fetched, value)) = *entry {
+            if fetched.elapsed() < self.positions_value_ttl {
+                tracing::debug!(proxy_address, "使用缓存的持仓价值");
+                return Ok(value);
+            }
+        }
+        let value = self.retrying("持仓价值", proxy_address, || self.fetch_positions_value(proxy_address)).await?;
+        *entry = Some((std::time::Instant::now(), value));
+        Ok(value)
+    }
+
+    async fn fetch_positions_value(&self, proxy_address: &str) -> Result<Decimal, AppError> {
+        let url = format!("{}/value?user={}", self.data_api_url, proxy_address);
         
-        let result = contract.balanceOf(wallet_addr)
-            .call()
+        let resp = self.http_client
+            .get(&url)
+            .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7)")
+            .send()
             .await
-            .map_err(|e| AppError::RpcError(format!("{}", e)))?;
+            .map_err(|e| {
+                telemetry::record_data_api_error("value", None);
+                AppError::ApiError(format!("{}", e), None)
+            })?;
 
-        // USDC有6位小数
-        let balance_f64 = result.to_string().parse::<f64>().unwrap_or(0.0) / 1_000_000.0;
-        Ok(balance_f64)
+        // 非成功状态码（包括 404）带上状态码返回，由 retrying 决定是否重试（429 和 5xx 重试，其他 4xx 不重试），
+        // 再由 fetch_portfolio 把这部分标记为获取失败，不把 0 当成真实的持仓价值
+        let status = resp.status();
+        if !status.is_success() {
+            tracing::warn!(proxy_address, status = status.as_u16(), "data-api /value 返回非成功状态码");
+            telemetry::record_data_api_error("value", Some(status.as_u16()));
+            return Err(AppError::ApiError(format!("data-api 返回状态码 {}", status), Some(status.as_u16())));
+        }
+        if status == reqwest::StatusCode::NO_CONTENT {
+            return Ok(Decimal::ZERO);
+        }
+
+        // 没有持仓时可能返回空响应体
+        let body = resp.bytes()
+            .await
+            .map_err(|e| AppError::ApiError(format!("{}", e), None))?;
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Decimal::ZERO);
+        }
+        let data: serde_json::Value = match serde_json::from_slice(&body) {
+            Ok(data) => data,
+            Err(e) => {
+                let preview = body_preview(&body);
+                tracing::warn!(proxy_address, status = %status, body = %preview, "data-api /value 返回的不是 JSON");
+                if self.non_json_as_zero {
+                    return Ok(Decimal::ZERO);
+                }
+                // 多半是上游故障时的错误页，按 ApiError 处理以便重试
+                return Err(AppError::ApiError(format!("data-api 返回的不是 JSON（{}）: {}", e, preview), None));
+            }
+        };
+
+        Ok(extract_positions_value(&data, &self.value_fields))
     }
 
-    async fn get_positions_value(&self, proxy_address: &str) -> Result<f64, AppError> {
-        let url = format!("{}/value?user={}", DATA_API_URL, proxy_address);
-        
+    // 解析错误重试也不会成功，直接返回；其余错误在用完尝试次数后返回最后一次的错误
+    async fn retrying<T, F, Fut>(&self, what: &str, address: &str, mut call: F) -> Result<T, AppError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, AppError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match call().await {
+                Err(e) if attempt < self.retry_attempts && e.is_retryable() => {
+                    // 被限流时多等一倍再重试
+                    let mut delay = backoff_delay(self.retry_base_delay, attempt);
+                    if e.upstream_status() == Some(429) {
+                        delay *= 2;
+                    }
+                    tracing::warn!(
+                        "获取 {} 的{}失败（第 {} 次），{} 毫秒后重试: {}",
+                        address, what, attempt, delay.as_millis(), e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // 逐页获取持仓，直到某页不足一整页
+    pub async fn get_positions(&self, proxy_address: &str) -> Result<Vec<Position>, AppError> {
+        let (positions, truncated) = collect_pages(POSITIONS_PAGE_SIZE, MAX_POSITIONS_PAGES, |offset| {
+            self.get_positions_page(proxy_address, offset)
+        }).await?;
+        if truncated {
+            tracing::warn!("{} 的持仓超过 {} 页，只统计前 {} 条", proxy_address, MAX_POSITIONS_PAGES, positions.len());
+        }
+        Ok(positions)
+    }
+
+    async fn get_positions_page(&self, proxy_address: &str, offset: usize) -> Result<Vec<Position>, AppError> {
+        let url = format!(
+            "{}/positions?user={}&limit={}&offset={}",
+            self.data_api_url, proxy_address, POSITIONS_PAGE_SIZE, offset
+        );
+
         let resp = self.http_client
             .get(&url)
             .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7)")
             .send()
             .await
-            .map_err(|e| AppError::ApiError(format!("{}", e)))?;
+            .map_err(|e| {
+                telemetry::record_data_api_error("positions", None);
+                AppError::ApiError(format!("{}", e), None)
+            })?;
 
-        if !resp.status().is_success() {
-            return Ok(0.0);
+        let status = resp.status();
+        if !status.is_success() {
+            tracing::warn!(proxy_address, status = status.as_u16(), "data-api /positions 返回非成功状态码");
+            telemetry::record_data_api_error("positions", Some(status.as_u16()));
+            return Err(AppError::ApiError(format!("持仓接口返回状态码 {}", status), Some(status.as_u16())));
+        }
+        if status == reqwest::StatusCode::NO_CONTENT {
+            return Ok(Vec::new());
         }
 
-        let data: serde_json::Value = resp.json()
+        let body = resp.bytes()
             .await
+            .map_err(|e| AppError::ApiError(format!("{}", e), None))?;
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice::<Vec<Position>>(&body).map_err(|e| {
+            // 响应本身不是 JSON 时附上开头部分，便于判断是不是故障页
+            if serde_json::from_slice::<serde_json::Value>(&body).is_err() {
+                AppError::ApiError(format!("持仓接口返回的不是 JSON（{}）: {}", e, body_preview(&body)), None)
+            } else {
+                AppError::ParseError(format!("{}", e))
+            }
+        })
+    }
+
+    // 只读查询，不会发送任何交易
+    pub async fn get_approvals(&self, owner: &str) -> Result<Vec<ApprovalStatus>, AppError> {
+        let owner_addr: Address = owner.parse()
+            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+        let usdc_addr: Address = self.chain.contracts().usdc.parse()
+            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+        let ctf_addr: Address = self.chain.contracts().conditional_tokens.parse()
+            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+        let decimals = self.decimals(&self.usdc).await;
+
+        self.on_rpc(|provider| async move {
+            let usdc = IERC20::new(usdc_addr, &provider);
+            let ctf = IERC1155::new(ctf_addr, &provider);
+
+            let mut statuses = Vec::new();
+            for (name, spender) in self.chain.contracts().spenders {
+                let spender_addr: Address = spender.parse()
+                    .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+
+                let allowance = usdc.allowance(owner_addr, spender_addr)
+                    .call()
+                    .await
+                    .map_err(AppError::from)?;
+                let ctf_approved = ctf.isApprovedForAll(owner_addr, spender_addr)
+                    .call()
+                    .await
+                    .map_err(AppError::from)?;
+
+                statuses.push(ApprovalStatus {
+                    spender: name.to_string(),
+                    spender_address: spender.to_string(),
+                    usdc_allowance: token_amount_f64(allowance, decimals),
+                    usdc_approved: !allowance.is_zero(),
+                    ctf_approved,
+                });
+            }
+
+            Ok(statuses)
+        }).await
+    }
+
+    // 估算尚未完成的授权交易所需的 gas，并返回当前 gas 价格（wei）；
+    // 单笔估算失败只记录在对应条目上，gas 价格获取失败时返回 None
+    pub async fn estimate_approval_gas(&self, owner: &str) -> Result<(Vec<GasEstimate>, Option<u128>), AppError> {
+        let statuses = self.get_approvals(owner).await?;
+
+        let owner_addr: Address = owner.parse()
+            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+        let usdc_addr: Address = self.chain.contracts().usdc.parse()
+            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+        let ctf_addr: Address = self.chain.contracts().conditional_tokens.parse()
+            .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+
+        // 估算失败只记录在单个条目上，不做节点切换，直接使用当前优先的节点
+        let provider = self.current_provider();
+        let usdc = IERC20::new(usdc_addr, provider);
+        let ctf = IERC1155::new(ctf_addr, provider);
+
+        let mut estimates = Vec::new();
+        for status in &statuses {
+            let spender_addr: Address = status.spender_address.parse()
+                .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+
+            if !status.usdc_approved {
+                let gas = usdc.approve(spender_addr, U256::MAX).from(owner_addr).estimate_gas().await;
+                estimates.push(gas_estimate(status, "usdc_approve", gas));
+            }
+            if !status.ctf_approved {
+                let gas = ctf.setApprovalForAll(spender_addr, true).from(owner_addr).estimate_gas().await;
+                estimates.push(gas_estimate(status, "ctf_set_approval_for_all", gas));
+            }
+        }
+
+        let gas_price = match provider.get_gas_price().await {
+            Ok(price) => Some(price),
+            Err(e) => {
+                tracing::warn!("获取 gas 价格失败: {}", e);
+                None
+            }
+        };
+
+        Ok((estimates, gas_price))
+    }
+
+    // 用 balanceOfBatch 一次查询 data-api 返回的所有持仓代币的链上余额。
+    // ERC-1155 无法直接枚举持有的代币，因此只能核对接口列出的代币，完全没被接口列出的持仓发现不了
+    pub async fn verify_ctf_positions(&self, proxy_address: &str) -> Result<CtfReport, AppError> {
+        let positions: Vec<Position> = self.get_positions(proxy_address)
+            .await?
+            .into_iter()
+            .filter(|p| !p.asset.is_empty())
+            .collect();
+
+        let owner: Address = proxy_address.parse()
             .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+        let ids = positions.iter()
+            .map(|p| p.asset.parse::<U256>().map_err(|e| AppError::ParseError(format!("无效的代币 id {}: {}", p.asset, e))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let balances = if ids.is_empty() {
+            Vec::new()
+        } else {
+            let ctf_addr: Address = self.chain.contracts().conditional_tokens.parse()
+                .map_err(|e| AppError::ParseError(format!("{}", e)))?;
+            self.on_rpc(|provider| {
+                let ids = ids.clone();
+                async move {
+                    IERC1155::new(ctf_addr, provider)
+                        .balanceOfBatch(vec![owner; ids.len()], ids)
+                        .call()
+                        .await
+                        .map_err(AppError::from)
+                }
+            }).await?
+        };
+
+        // CTF 代币由 USDC 拆分而来，小数位数与 USDC 相同
+        let decimals = self.decimals(&self.usdc).await;
+        let discrepancies = positions.iter()
+            .zip(&balances)
+            .filter_map(|(position, balance)| {
+                // 超出 Decimal 范围的余额不可能与接口数量一致，按数量不一致报告
+                let onchain_size = token_amount(*balance, decimals).unwrap_or(Decimal::MAX);
+                let kind = if position.size > Decimal::ZERO && onchain_size.is_zero() {
+                    "ghost"
+                } else if position.size <= Decimal::ZERO && onchain_size > Decimal::ZERO {
+                    "unreported"
+                } else if (position.size - onchain_size).abs() > CTF_SIZE_TOLERANCE {
+                    "size_mismatch"
+                } else {
+                    return None;
+                };
+                Some(CtfDiscrepancy {
+                    asset: position.asset.clone(),
+                    title: position.title.clone(),
+                    api_size: position.size,
+                    onchain_size,
+                    kind,
+                })
+            })
+            .collect();
+
+        Ok(CtfReport {
+            proxy_address: proxy_address.to_string(),
+            checked_at: chrono::Utc::now().timestamp_millis(),
+            checked: positions.len(),
+            discrepancies,
+        })
+    }
+
+    // 可变现价值：假设每个持仓立刻以市价全部卖出。
+    // 从最高买价开始逐档吃单，每档成交 min(剩余数量, 该档数量)，价值累加 价格 × 成交量；
+    // 买盘深度不足以吃完的剩余数量按 0 计价，因此结果是保守估计，不会高于按中间价计算的价值。
+    async fn get_liquidation_value(&self, proxy_address: &str) -> Result<Decimal, AppError> {
+        let positions = self.get_positions(proxy_address).await?;
+        let mut total = Decimal::ZERO;
+
+        for position in positions.iter().filter(|p| p.size > Decimal::ZERO && !p.asset.is_empty()) {
+            let book = self.get_order_book(&position.asset).await?;
+            total += walk_bids(&book.bids, position.size);
+        }
+
+        Ok(total)
+    }
+
+    // 与 rs-clob-client 的 order_book 请求同一个接口，只读取买盘
+    async fn get_order_book(&self, token_id: &str) -> Result<OrderBook, AppError> {
+        let url = format!("{}/book?token_id={}", CLOB_API_URL, token_id);
+
+        let resp = self.http_client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| AppError::ApiError(format!("{}", e), None))?;
+
+        // 已结算或下架的市场没有订单簿，视为没有买盘
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(OrderBook { bids: Vec::new() });
+        }
+        if !resp.status().is_success() {
+            return Err(AppError::ApiError(format!("订单簿接口返回状态码 {}", resp.status()), Some(resp.status().as_u16())));
+        }
+
+        resp.json::<OrderBook>()
+            .await
+            .map_err(|e| AppError::ParseError(format!("{}", e)))
+    }
+}
+
+// 响应可能是列表或字典；每个对象把其中出现的所选字段相加，列表中所有条目（如分段返回的结果）的和再累加，
+// 不包含任何所选字段的条目跳过，非数字的字段按 0 计
+fn extract_positions_value(data: &serde_json::Value, fields: &[String]) -> Decimal {
+    let sum_fields = |item: &serde_json::Value| -> Option<Decimal> {
+        let values: Vec<Decimal> = fields
+            .iter()
+            .filter_map(|field| item.get(field))
+            .map(|value| json_number(value).unwrap_or_else(|| {
+                tracing::warn!("持仓价值字段不是数字: {}", value);
+                Decimal::ZERO
+            }))
+            .collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.iter().sum())
+        }
+    };
+
+    match data.as_array() {
+        Some(arr) => arr.iter().filter_map(sum_fields).sum(),
+        None => sum_fields(data).unwrap_or_default(),
+    }
+}
+
+fn gas_estimate(
+    status: &ApprovalStatus,
+    action: &'static str,
+    gas: Result<u64, alloy::contract::Error>,
+) -> GasEstimate {
+    let (gas, error) = match gas {
+        Ok(gas) => (Some(gas), None),
+        Err(e) => {
+            tracing::warn!("估算 {} 对 {} 的 gas 失败: {}", action, status.spender, e);
+            (None, Some(e.to_string()))
+        }
+    };
+    GasEstimate {
+        spender: status.spender.clone(),
+        spender_address: status.spender_address.clone(),
+        action,
+        gas,
+        error,
+    }
+}
+
+// 持有的不同市场数量，已清零的持仓不计入
+fn count_markets(positions: &[Position]) -> usize {
+    let markets: std::collections::HashSet<&str> = positions
+        .iter()
+        .filter(|p| p.size > Decimal::ZERO)
+        .map(|p| p.condition_id.as_str())
+        .collect();
+    markets.len()
+}
+
+// 部分接口会把数字编码成字符串，例如 "value": "123.45"，两种形式都接受；
+// 按十进制文本直接解析成 Decimal，不经过浮点数，科学计数法（如 1e-7）也接受
+fn json_number(value: &serde_json::Value) -> Option<Decimal> {
+    let text = match value {
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::String(s) => s.trim().to_string(),
+        _ => return None,
+    };
+    text.parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(&text))
+        .ok()
+}
+
+fn deserialize_number<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    match value {
+        serde_json::Value::Null => Ok(Decimal::ZERO),
+        other => json_number(&other)
+            .ok_or_else(|| serde::de::Error::custom(format!("无法解析为数字: {}", other))),
+    }
+}
+
+fn paper_portfolio(wallet: &WalletConfig) -> PortfolioData {
+    let usdc_balance = wallet.paper_usdc_balance.unwrap_or_default();
+    let positions_value = wallet.paper_positions_value.unwrap_or_default();
+    PortfolioData {
+        proxy_address: wallet.proxy_address.clone(),
+        usdc_balance,
+        positions_value,
+        portfolio_total: usdc_balance + positions_value,
+        last_updated: chrono::Utc::now().timestamp_millis(),
+        paper: true,
+        ..Default::default()
+    }
+}
+
+// base * 2^(attempt-1)，再加上最多一半的随机抖动，避免多个钱包同时重试
+fn backoff_delay(base: std::time::Duration, attempt: u32) -> std::time::Duration {
+    let delay = base.saturating_mul(2u32.saturating_pow(attempt - 1));
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let jitter = delay.as_millis() as u64 / 2 * (nanos % 1000) as u64 / 1000;
+    delay + std::time::Duration::from_millis(jitter)
+}
+
+// 按 offset 逐页调用 fetch_page，直到某页不足 page_size 条；第二个返回值表示是否因达到 max_pages 而没有取完
+async fn collect_pages<T, F, Fut>(page_size: usize, max_pages: usize, mut fetch_page: F) -> Result<(Vec<T>, bool), AppError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<T>, AppError>>,
+{
+    let mut items = Vec::new();
+    for page in 0..max_pages {
+        let batch = fetch_page(page * page_size).await?;
+        let last_page = batch.len() < page_size;
+        items.extend(batch);
+        if last_page {
+            return Ok((items, false));
+        }
+    }
+    Ok((items, true))
+}
+
+// 响应体开头最多 200 个字符，用于日志和错误信息
+fn body_preview(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).trim().chars().take(200).collect()
+}
+
+// 链上的整数数量按精度换算，全程使用 Decimal，不经过浮点数
+fn token_amount(raw: U256, decimals: u8) -> Result<Decimal, AppError> {
+    let raw = i128::try_from(raw)
+        .map_err(|_| AppError::ParseError(format!("代币数量超出范围: {}", raw)))?;
+    Decimal::try_from_i128_with_scale(raw, decimals as u32)
+        .map(|amount| amount.normalize())
+        .map_err(|e| AppError::ParseError(format!("代币数量超出范围: {}: {}", raw, e)))
+}
+
+// 授权额度可能是 U256::MAX，超出 Decimal 的范围，只用于展示时按浮点数换算
+fn token_amount_f64(raw: U256, decimals: u8) -> f64 {
+    raw.to_string().parse::<f64>().unwrap_or(0.0) / 10f64.powi(decimals as i32)
+}
+
+// alloy 依赖的 reqwest 版本与我们不同，无法共用客户端，按同一份配置单独创建
+fn rpc_endpoint(config: &HttpConfig, url: reqwest::Url) -> (reqwest::Url, DynProvider) {
+    let provider = ProviderBuilder::new()
+        .with_reqwest(url.clone(), |builder| {
+            builder
+                .timeout(config.timeout)
+                .connect_timeout(config.connect_timeout)
+                .pool_idle_timeout(config.pool_idle_timeout)
+                .pool_max_idle_per_host(config.pool_max_idle_per_host)
+                .build()
+                .unwrap()
+        })
+        .erased();
+    (url, provider)
+}
+
+fn walk_bids(bids: &[BookLevel], size: Decimal) -> Decimal {
+    let mut levels: Vec<(Decimal, Decimal)> = bids
+        .iter()
+        .filter_map(|level| Some((level.price.trim().parse().ok()?, level.size.trim().parse().ok()?)))
+        .collect();
+    levels.sort_by_key(|level| std::cmp::Reverse(level.0));
+
+    let mut remaining = size;
+    let mut value = Decimal::ZERO;
+    for (price, level_size) in levels {
+        if remaining <= Decimal::ZERO {
+            break;
+        }
+        let filled = remaining.min(level_size);
+        value += filled * price;
+        remaining -= filled;
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{call_data, dead_url, uint_result, MockResponse, MockServer};
+    use std::sync::Mutex;
+
+    const PROXY: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    const SIGNER: &str = "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359";
+
+    fn service(rpc_urls: Vec<reqwest::Url>) -> PortfolioService {
+        let http = HttpConfig {
+            timeout: std::time::Duration::from_secs(5),
+            connect_timeout: std::time::Duration::from_secs(1),
+            pool_idle_timeout: std::time::Duration::from_secs(30),
+            pool_max_idle_per_host: 4,
+        };
+        PortfolioService::with_config(&http).with_rpc_urls(rpc_urls)
+    }
+
+    // 代理钱包有 100 USDC、签名地址有 25.5 USDC；decimals() 返回 6
+    async fn usdc_node() -> MockServer {
+        MockServer::rpc(|method, params| {
+            let data = call_data(params);
+            match method {
+                "eth_call" if data.starts_with("0x313ce567") => uint_result(6),
+                "eth_call" if data.ends_with(&PROXY[2..].to_lowercase()) => uint_result(100_000_000),
+                "eth_call" if data.ends_with(&SIGNER[2..].to_lowercase()) => uint_result(25_500_000),
+                _ => serde_json::Value::Null,
+            }
+        }).await
+    }
+
+    fn wallet(signer_address: Option<&str>) -> WalletConfig {
+        WalletConfig {
+            wallet_id: "w1".to_string(),
+            name: "测试".to_string(),
+            proxy_address: PROXY.to_string(),
+            signer_address: signer_address.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn signer_usdc_is_added_to_the_proxy_balance() {
+        let node = usdc_node().await;
+        let data_api = MockServer::start(|_, _| MockResponse::json(serde_json::json!([{ "user": PROXY, "value": 10 }]))).await;
+        let service = service(vec![node.url.clone()]).with_data_api_url(data_api.url.to_string());
+
+        let combined = service.fetch_portfolio(&wallet(Some(SIGNER)), Components::ALL, None, &UsdcBalances::new()).await.unwrap();
+        assert_eq!(combined.usdc_balance, Decimal::new(1255, 1));
+        assert_eq!(combined.signer_usdc_balance, Some(Decimal::new(255, 1)));
+        assert_eq!(combined.positions_value, Decimal::from(10));
+        assert_eq!(combined.portfolio_total, Decimal::new(1355, 1));
+        assert!(!combined.partial);
+
+        // 未配置签名地址时只统计代理钱包
+        let proxy_only = service.fetch_portfolio(&wallet(None), Components::ALL, None, &UsdcBalances::new()).await.unwrap();
+        assert_eq!(proxy_only.usdc_balance, Decimal::from(100));
+        assert_eq!(proxy_only.signer_usdc_balance, None);
+    }
+
+    // 节点和 HTTP 连接在构造时建好，之后的查询都复用同一个连接
+    #[tokio::test]
+    async fn balance_calls_reuse_the_provider_built_at_construction() {
+        let node = usdc_node().await;
+        let service = service(vec![node.url.clone()]);
+        assert_eq!(service.rpc_endpoints.len(), 1);
+
+        for _ in 0..5 {
+            assert_eq!(service.get_usdc_balance(PROXY).await.unwrap(), Decimal::from(100));
+        }
+        assert_eq!(node.connections(), 1);
+    }
+
+    // 前两次请求返回 503，之后按 respond 返回
+    async fn flaky_server<F>(respond: F) -> (MockServer, Arc<AtomicUsize>)
+    where
+        F: Fn(&[u8]) -> MockResponse + Send + Sync + 'static,
+    {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = MockServer::start(move |_, body| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                MockResponse::with_status(503, "text/plain", "Service Unavailable")
+            } else {
+                respond(body)
+            }
+        }).await;
+        (server, requests)
+    }
+
+    #[tokio::test]
+    async fn positions_value_is_retried_until_the_api_recovers() {
+        let (data_api, requests) = flaky_server(|_| MockResponse::json(serde_json::json!({ "value": 42 }))).await;
+        let three_attempts = service(Vec::new()).with_data_api_url(data_api.url.to_string()).with_retry(3, 1);
+
+        assert_eq!(three_attempts.get_positions_value(PROXY).await.unwrap(), Decimal::from(42));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // 尝试次数不够时返回最后一次的错误，保留原来的错误类型和状态码
+        let (data_api, _) = flaky_server(|_| MockResponse::json(serde_json::json!({ "value": 42 }))).await;
+        let two_attempts = service(Vec::new()).with_data_api_url(data_api.url.to_string()).with_retry(2, 1);
+        let err = two_attempts.get_positions_value(PROXY).await.unwrap_err();
+        assert!(matches!(err, AppError::ApiError(_, Some(503))), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn usdc_balance_is_retried_after_node_errors() {
+        let (node, requests) = flaky_server(|body| {
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            let result = if call_data(&request["params"]).starts_with("0x313ce567") { uint_result(6) } else { uint_result(100_000_000) };
+            MockResponse::rpc_result(&request, result)
+        }).await;
+        let service = service(vec![node.url.clone()]).with_retry(3, 1);
+
+        assert_eq!(service.get_usdc_balance(PROXY).await.unwrap(), Decimal::from(100));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn backoff_doubles_with_up_to_half_jitter() {
+        let base = std::time::Duration::from_millis(100);
+        for (attempt, expected) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = backoff_delay(base, attempt).as_millis();
+            assert!((expected..=expected * 3 / 2).contains(&delay), "第 {} 次: {}", attempt, delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn failover_skips_a_dead_endpoint_and_prefers_the_live_one() {
+        let node = usdc_node().await;
+        let service = service(vec![dead_url().await, node.url.clone()]);
+
+        assert_eq!(service.get_usdc_balance(PROXY).await.unwrap(), Decimal::from(100));
+        assert_eq!(service.preferred_rpc.load(Ordering::Relaxed), 1);
+
+        // 之后的调用直接从可用的节点开始
+        assert_eq!(service.get_usdc_balance(SIGNER).await.unwrap(), Decimal::new(255, 1));
+        assert_eq!(service.preferred_rpc.load(Ordering::Relaxed), 1);
+        assert_eq!(node.connections(), 1);
+    }
+
+    // 模拟支持（或不支持）Multicall3 的节点；direct 统计直接发给 USDC 合约的 balanceOf 调用次数
+    async fn multicall_node(multicall_reverts: bool, direct: Arc<AtomicUsize>) -> MockServer {
+        use alloy::sol_types::SolCall;
 
-        // 响应可能是列表或字典
-        if let Some(arr) = data.as_array() {
-            for item in arr {
-                if let Some(value) = item.get("value") {
-                    return Ok(value.as_f64().unwrap_or(0.0));
+        let balance_of = |owner: Address| -> U256 {
+            if owner == PROXY.parse::<Address>().unwrap() { U256::from(100_000_000u64) } else { U256::from(25_500_000u64) }
+        };
+        MockServer::start(move |_, body| {
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            let params = &request["params"];
+            let input = alloy::primitives::hex::decode(call_data(params)).unwrap_or_default();
+            let to: Address = params[0]["to"].as_str().and_then(|to| to.parse().ok()).unwrap_or_default();
+            if input.starts_with(&IERC20::decimalsCall::SELECTOR) {
+                return MockResponse::rpc_result(&request, uint_result(6));
+            }
+            if to == MULTICALL3_ADDRESS {
+                if multicall_reverts {
+                    return MockResponse::json(serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "error": { "code": 3, "message": "execution reverted" }
+                    }));
                 }
+                let calls = IMulticall3::aggregate3Call::abi_decode(&input).unwrap().calls;
+                let results: Vec<IMulticall3::Result3> = calls
+                    .iter()
+                    .map(|call| {
+                        let owner = IERC20::balanceOfCall::abi_decode(&call.callData).unwrap().owner;
+                        IMulticall3::Result3 {
+                            success: true,
+                            returnData: IERC20::balanceOfCall::abi_encode_returns(&balance_of(owner)).into(),
+                        }
+                    })
+                    .collect();
+                let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&results);
+                return MockResponse::rpc_result(&request, serde_json::json!(alloy::primitives::hex::encode_prefixed(encoded)));
             }
-        } else if let Some(value) = data.get("value") {
-            return Ok(value.as_f64().unwrap_or(0.0));
+            direct.fetch_add(1, Ordering::SeqCst);
+            let owner = IERC20::balanceOfCall::abi_decode(&input).unwrap().owner;
+            let encoded = IERC20::balanceOfCall::abi_encode_returns(&balance_of(owner));
+            MockResponse::rpc_result(&request, serde_json::json!(alloy::primitives::hex::encode_prefixed(encoded)))
+        }).await
+    }
+
+    #[tokio::test]
+    async fn batch_balances_come_from_one_multicall() {
+        let direct = Arc::new(AtomicUsize::new(0));
+        let node = multicall_node(false, direct.clone()).await;
+        let addresses = [PROXY.parse().unwrap(), SIGNER.parse().unwrap()];
+
+        let balances = service(vec![node.url.clone()]).get_usdc_balances_batch(&addresses).await.unwrap();
+        assert_eq!(balances, vec![Decimal::from(100), Decimal::new(255, 1)]);
+        assert_eq!(direct.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn batch_balances_fall_back_when_the_multicall_reverts() {
+        let direct = Arc::new(AtomicUsize::new(0));
+        let node = multicall_node(true, direct.clone()).await;
+        let addresses = [PROXY.parse().unwrap(), SIGNER.parse().unwrap()];
+
+        let balances = service(vec![node.url.clone()]).get_usdc_balances_batch(&addresses).await.unwrap();
+        assert_eq!(balances, vec![Decimal::from(100), Decimal::new(255, 1)]);
+        assert_eq!(direct.load(Ordering::SeqCst), 2);
+    }
+
+    const ERROR_PAGE: &str = "<html><head><title>502 Bad Gateway</title></head><body>cloudflare</body></html>";
+
+    #[tokio::test]
+    async fn html_error_pages_are_api_errors_with_a_preview() {
+        let data_api = MockServer::start(|_, _| MockResponse::with_status(200, "text/html", ERROR_PAGE)).await;
+        let strict = service(Vec::new()).with_data_api_url(data_api.url.to_string());
+
+        let err = strict.get_positions_value(PROXY).await.unwrap_err();
+        assert!(matches!(err, AppError::ApiError(_, None)), "{:?}", err);
+        assert!(err.to_string().contains("502 Bad Gateway"), "{}", err);
+        assert!(err.is_retryable());
+
+        let err = strict.get_positions(PROXY).await.unwrap_err();
+        assert!(matches!(err, AppError::ApiError(_, None)), "{:?}", err);
+
+        // 开启 NON_JSON_AS_ZERO 后按没有持仓处理
+        let lenient = service(Vec::new()).with_data_api_url(data_api.url.to_string()).with_non_json_as_zero(true);
+        assert_eq!(lenient.get_positions_value(PROXY).await.unwrap(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn body_preview_keeps_the_first_200_characters() {
+        let body = format!("  {}  ", "页".repeat(300));
+        assert_eq!(body_preview(body.as_bytes()).chars().count(), 200);
+        assert_eq!(body_preview(ERROR_PAGE.as_bytes()), ERROR_PAGE);
+    }
+
+    #[tokio::test]
+    async fn not_found_is_an_error_and_marks_the_refresh_partial() {
+        let data_api = MockServer::start(|_, _| MockResponse::with_status(404, "application/json", r#"{"error":"not found"}"#)).await;
+        let node = usdc_node().await;
+        let service = service(vec![node.url.clone()]).with_data_api_url(data_api.url.to_string()).with_retry(3, 1);
+
+        let err = service.get_positions_value(PROXY).await.unwrap_err();
+        assert!(matches!(err, AppError::ApiError(_, Some(404))), "{:?}", err);
+        assert!(!err.is_retryable());
+        let err = service.get_positions(PROXY).await.unwrap_err();
+        assert_eq!(err.upstream_status(), Some(404));
+
+        // 持仓部分获取失败，结果标记为 partial，不会作为真实的 0 持仓写入历史
+        let data = service.fetch_portfolio(&wallet(None), Components::ALL, None, &UsdcBalances::new()).await.unwrap();
+        assert!(data.partial);
+        assert_eq!(data.carried_forward, vec!["positions"]);
+        assert_eq!(data.usdc_balance, Decimal::from(100));
+    }
+
+    #[tokio::test]
+    async fn components_without_a_previous_value_mark_the_result_partial() {
+        let node = usdc_node().await;
+        let data_api = MockServer::start(|_, _| MockResponse::json(serde_json::json!({ "value": 10 }))).await;
+        let service = service(vec![node.url.clone()]).with_data_api_url(data_api.url.to_string());
+        let usdc_only = Components { usdc: true, positions: false };
+
+        // 没有缓存可沿用，持仓价值只能填 0
+        let cold = service.fetch_portfolio(&wallet(None), usdc_only, None, &UsdcBalances::new()).await.unwrap();
+        assert!(cold.partial);
+        assert_eq!(cold.carried_forward, vec!["positions"]);
+        assert_eq!(cold.positions_value, Decimal::ZERO);
+
+        // 有上一次的值时照常沿用，不算 partial
+        let previous = PortfolioData { positions_value: Decimal::from(7), ..Default::default() };
+        let warm = service.fetch_portfolio(&wallet(None), usdc_only, Some(&previous), &UsdcBalances::new()).await.unwrap();
+        assert!(!warm.partial);
+        assert_eq!(warm.positions_value, Decimal::from(7));
+        assert_eq!(warm.portfolio_total, Decimal::from(107));
+    }
+
+    #[tokio::test]
+    async fn unreachable_node_and_reverted_call_are_distinct_errors() {
+        let err = service(vec![dead_url().await]).get_usdc_balance(PROXY).await.unwrap_err();
+        assert!(matches!(err, AppError::RpcConnectError(_)), "{:?}", err);
+
+        let node = MockServer::start(|_, body| {
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            MockResponse::json(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": 3, "message": "execution reverted" }
+            }))
+        }).await;
+        let err = service(vec![node.url.clone()]).get_usdc_balance(PROXY).await.unwrap_err();
+        assert!(matches!(err, AppError::RpcError(_)), "{:?}", err);
+    }
+
+    fn fields(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn value_field_strategies_over_a_sample_payload() {
+        let payload = serde_json::json!([
+            { "user": PROXY, "value": 120.5, "cashPnl": -20.25, "pnl": 4 },
+            { "user": PROXY, "value": "30" }
+        ]);
+
+        // 默认只取 value，列表中的条目相加
+        assert_eq!(extract_positions_value(&payload, &fields(&["value"])), Decimal::new(1505, 1));
+        // 多个字段相加，缺少该字段的条目只计已有的字段
+        assert_eq!(extract_positions_value(&payload, &fields(&["value", "cashPnl"])), Decimal::new(13025, 2));
+        // 没有条目包含所选字段
+        assert_eq!(extract_positions_value(&payload, &fields(&["realizedPnl"])), Decimal::ZERO);
+        // 字典形式的响应
+        let single = serde_json::json!({ "value": 7, "pnl": 1.5 });
+        assert_eq!(extract_positions_value(&single, &fields(&["value", "pnl"])), Decimal::new(85, 1));
+    }
+
+    #[test]
+    fn numbers_may_be_encoded_as_strings() {
+        assert_eq!(json_number(&serde_json::json!(123.45)), Some(Decimal::new(12345, 2)));
+        assert_eq!(json_number(&serde_json::json!(" 123.45 ")), Some(Decimal::new(12345, 2)));
+        assert_eq!(json_number(&serde_json::json!("1e-7")), Some(Decimal::new(1, 7)));
+        assert_eq!(json_number(&serde_json::json!("n/a")), None);
+
+        // 非数字字符串计为 0 并记录警告，不影响其他字段
+        let payload = serde_json::json!({ "value": "n/a", "pnl": "2.5" });
+        assert_eq!(extract_positions_value(&payload, &fields(&["value", "pnl"])), Decimal::new(25, 1));
+    }
+
+    #[test]
+    fn position_amounts_accept_numbers_and_strings() {
+        let position: Position = serde_json::from_value(serde_json::json!({
+            "size": 10, "avgPrice": "0.42", "curPrice": null, "currentValue": "4.2"
+        })).unwrap();
+        assert_eq!(position.size, Decimal::from(10));
+        assert_eq!(position.avg_price, Decimal::new(42, 2));
+        assert_eq!(position.cur_price, Decimal::ZERO);
+        assert_eq!(position.current_value, Decimal::new(42, 1));
+
+        let invalid = serde_json::from_value::<Position>(serde_json::json!({ "size": "lots" }));
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn collect_pages_follows_offsets_until_a_short_page() {
+        let requested = Mutex::new(Vec::new());
+        let (items, truncated) = collect_pages(POSITIONS_PAGE_SIZE, MAX_POSITIONS_PAGES, |offset| {
+            requested.lock().unwrap().push(offset);
+            // 第一页是满页，第二页只有 120 条
+            let len = if offset == 0 { POSITIONS_PAGE_SIZE } else { 120 };
+            async move { Ok::<_, AppError>(vec![offset; len]) }
+        }).await.unwrap();
+
+        assert_eq!(*requested.lock().unwrap(), vec![0, POSITIONS_PAGE_SIZE]);
+        assert_eq!(items.len(), POSITIONS_PAGE_SIZE + 120);
+        assert!(!truncated);
+    }
+
+    #[tokio::test]
+    async fn get_positions_reads_two_pages_from_the_data_api() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let paths = requested.clone();
+        let data_api = MockServer::start(move |path, _| {
+            paths.lock().unwrap().push(path.to_string());
+            let len = if path.ends_with("&offset=0") { POSITIONS_PAGE_SIZE } else { 120 };
+            let page: Vec<_> = (0..len)
+                .map(|i| serde_json::json!({ "asset": format!("token-{}", i), "size": "2", "currentValue": 1.5 }))
+                .collect();
+            MockResponse::json(serde_json::json!(page))
+        }).await;
+        let service = service(Vec::new()).with_data_api_url(data_api.url.to_string());
+
+        let positions = service.get_positions(PROXY).await.unwrap();
+        assert_eq!(positions.len(), POSITIONS_PAGE_SIZE + 120);
+        assert_eq!(positions[0].size, Decimal::from(2));
+        assert_eq!(positions[0].current_value, Decimal::new(15, 1));
+        assert_eq!(*requested.lock().unwrap(), vec![
+            format!("/positions?user={}&limit=500&offset=0", PROXY),
+            format!("/positions?user={}&limit=500&offset=500", PROXY),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn no_content_and_empty_bodies_mean_no_positions() {
+        for (status, body) in [(204, ""), (200, ""), (200, " \n")] {
+            let data_api = MockServer::start(move |_, _| MockResponse::with_status(status, "application/json", body)).await;
+            let service = service(Vec::new()).with_data_api_url(data_api.url.to_string());
+
+            assert!(service.get_positions(PROXY).await.unwrap().is_empty(), "状态码 {} 响应体 {:?}", status, body);
+            assert_eq!(service.get_positions_value(PROXY).await.unwrap(), Decimal::ZERO);
         }
+    }
+
+    #[tokio::test]
+    async fn collect_pages_stops_at_max_pages() {
+        let (items, truncated) = collect_pages(2, 3, |offset| async move { Ok::<_, AppError>(vec![offset; 2]) })
+            .await
+            .unwrap();
 
-        Ok(0.0)
+        assert_eq!(items, vec![0, 0, 2, 2, 4, 4]);
+        assert!(truncated);
     }
 }
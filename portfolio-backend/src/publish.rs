@@ -0,0 +1,60 @@
+use crate::config::Settings;
+use crate::portfolio::PortfolioData;
+
+// 把保存成功的快照以 JSON 发布到 NATS，供下游数据平台消费。
+// 需要编译时开启 nats feature 并配置 NATS_URL；发布在后台进行，不阻塞刷新和 HTTP 响应，失败只记录日志
+pub struct Publisher {
+    #[cfg(feature = "nats")]
+    nats: Option<(async_nats::Client, String)>,
+}
+
+impl Publisher {
+    #[cfg(feature = "nats")]
+    pub async fn connect(settings: &Settings) -> Self {
+        let Some(url) = settings.nats_url.as_deref() else {
+            return Self { nats: None };
+        };
+        // 连不上时不影响启动，只是不发布
+        match async_nats::connect(url).await {
+            Ok(client) => {
+                tracing::info!("已连接 NATS，快照将发布到 {}", settings.nats_subject);
+                Self { nats: Some((client, settings.nats_subject.clone())) }
+            }
+            Err(e) => {
+                tracing::error!("连接 NATS 失败，快照不会发布: {}", e);
+                Self { nats: None }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "nats"))]
+    pub async fn connect(settings: &Settings) -> Self {
+        if settings.nats_url.is_some() {
+            tracing::warn!("已设置 NATS_URL，但编译时未开启 nats feature，快照不会发布到 {}", settings.nats_subject);
+        }
+        Self {}
+    }
+
+    #[cfg(feature = "nats")]
+    pub fn publish(&self, data: &PortfolioData) {
+        let Some((client, subject)) = self.nats.clone() else {
+            return;
+        };
+        let payload = match serde_json::to_vec(data) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::warn!(proxy_address = %data.proxy_address, error = %e, "序列化快照失败");
+                return;
+            }
+        };
+        let proxy_address = data.proxy_address.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client.publish(subject, payload.into()).await {
+                tracing::warn!(proxy_address = %proxy_address, error = %e, "发布快照到 NATS 失败");
+            }
+        });
+    }
+
+    #[cfg(not(feature = "nats"))]
+    pub fn publish(&self, _data: &PortfolioData) {}
+}
@@ -0,0 +1,137 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::portfolio::{PortfolioData, Position};
+
+// 主要接口的响应结构，字段名即前端读取的 JSON 字段，改名会破坏前端
+
+// /api/health
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthResponse {
+    // 进程在运行且能处理请求时固定为 "ok"，不检查依赖；依赖的检查见 /api/health/ready
+    pub status: &'static str,
+}
+
+// /api/portfolio/refresh
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RefreshResponse {
+    pub success: bool,
+    pub data: Vec<PortfolioData>,
+    pub total: Decimal,
+    // 没有任何钱包统计持仓数量时为 null
+    pub total_positions_count: Option<usize>,
+    pub timestamp: i64,
+}
+
+// /api/portfolio/cached，也是 /api/dashboard 的 cached 部分和实时推送的初始快照。
+// 各项合计始终包含零头钱包，hide_dust 只从 wallets 列表中去掉它们，去掉的个数见 dust_hidden
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CachedResponse {
+    pub wallets: Vec<PortfolioData>,
+    pub total_portfolio: Decimal,
+    pub total_usdc_balance: Decimal,
+    pub total_positions_value: Decimal,
+    pub total_positions_count: Option<usize>,
+    pub dust_hidden: usize,
+}
+
+// /api/portfolio/alerts 中的一条记录；name 来自当前的钱包配置，钱包已被删除时为 null
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlertEntry {
+    pub timestamp: i64,
+    pub proxy_address: String,
+    pub name: Option<String>,
+    pub old_value: Decimal,
+    pub new_value: Decimal,
+    pub pct_change: Decimal,
+}
+
+// /api/portfolio/summary 中的单个钱包
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WalletSummary {
+    pub proxy_address: String,
+    pub name: String,
+    pub portfolio_total: Decimal,
+    // 持有的不同市场数量，没有统计时为 null
+    pub positions_count: Option<usize>,
+    // 24 小时前附近没有快照的钱包省略变化字段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_24h: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_24h_pct: Option<Decimal>,
+    // 最近若干条快照的总价值都为 0，见 INACTIVE_LOOKBACK_SNAPSHOTS
+    pub inactive: bool,
+    // 总价值低于 MIN_PORTFOLIO_VALUE
+    pub dust: bool,
+    // 相对 cost_basis 的未实现盈亏，未配置成本的钱包省略
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pnl: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pnl_pct: Option<Decimal>,
+}
+
+// /api/portfolio/compare 中某一端实际使用的快照
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ComparePoint {
+    pub timestamp: i64,
+    pub portfolio_total: Decimal,
+}
+
+// /api/portfolio/compare 中的单个钱包；某一端在允许范围内没有快照时该端为 null，且省略差值
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WalletComparison {
+    pub proxy_address: String,
+    pub name: String,
+    pub from: Option<ComparePoint>,
+    pub to: Option<ComparePoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_pct: Option<Decimal>,
+}
+
+// /api/portfolio/compare；合计只统计两端都有快照的钱包
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CompareResponse {
+    pub from: i64,
+    pub to: i64,
+    pub total_from: Decimal,
+    pub total_to: Decimal,
+    pub delta: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_pct: Option<Decimal>,
+    pub wallets: Vec<WalletComparison>,
+}
+
+// /api/portfolio/summary。总价值、钱包数、持仓数量、24 小时变化和盈亏始终包含零头钱包，与 /api/portfolio/cached 一致；
+// hide_dust 时 wallets 以及 highest / lowest 只在其余钱包中选取，去掉的个数见 dust_hidden
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PortfolioSummary {
+    pub total_portfolio: Decimal,
+    pub wallet_count: usize,
+    // 所有钱包持有的市场数量之和，没有任何钱包统计持仓数量时为 null
+    pub total_positions_count: Option<usize>,
+    // 只统计有 24 小时前快照的钱包，所有钱包都没有时省略
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_24h: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_24h_pct: Option<Decimal>,
+    // 只统计配置了 cost_basis 的钱包，都没有配置时省略
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pnl: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pnl_pct: Option<Decimal>,
+    pub highest: Option<WalletSummary>,
+    pub lowest: Option<WalletSummary>,
+    pub wallets: Vec<WalletSummary>,
+    pub dust_hidden: usize,
+}
+
+// /api/portfolio/positions/{address}。total 为过滤和截断之前的持仓数量
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PositionsResponse {
+    pub proxy_address: String,
+    pub total: usize,
+    pub positions: Vec<Position>,
+}
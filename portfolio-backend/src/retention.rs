@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use crate::db;
+use crate::SharedState;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// 每天删除一次超过 RETENTION_DAYS 的快照，启动后先执行一次
+pub fn spawn(state: SharedState) {
+    let days = state.settings.retention_days;
+    if days == 0 {
+        return;
+    }
+    tracing::info!("快照保留 {} 天，每天清理一次", days);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match db::delete_snapshots_older_than(&state.db_pool, days).await {
+                Ok(deleted) => tracing::info!("已删除 {} 条超过 {} 天的快照", deleted, days),
+                Err(e) => {
+                    tracing::error!("清理过期快照失败: {}", e);
+                    state.errors.record("retention", None, &e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::db;
+use crate::portfolio::PortfolioData;
+use crate::SharedState;
+
+struct PendingSave {
+    data: PortfolioData,
+    attempts: u32,
+    next_at: Instant,
+}
+
+// 写入失败的快照在内存中排队重试，按原始时间戳写入，避免数据库短暂故障造成历史缺口
+pub struct SaveRetryQueue {
+    capacity: usize,
+    max_attempts: u32,
+    base_delay: Duration,
+    pending: Mutex<VecDeque<PendingSave>>,
+    // 达到重试上限或队列已满而丢弃的快照数量
+    dropped: AtomicU64,
+}
+
+impl SaveRetryQueue {
+    pub fn new(capacity: usize, max_attempts: u32, base_delay_ms: u64) -> Self {
+        Self {
+            capacity,
+            max_attempts,
+            base_delay: Duration::from_millis(base_delay_ms.max(1)),
+            pending: Mutex::new(VecDeque::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 0 && self.capacity > 0
+    }
+
+    pub fn enqueue(&self, data: PortfolioData) {
+        if !self.enabled() {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            tracing::error!("快照重试队列已满（{}），丢弃 {} 的快照", self.capacity, data.proxy_address);
+            return;
+        }
+        pending.push_back(PendingSave {
+            data,
+            attempts: 0,
+            next_at: Instant::now() + self.base_delay,
+        });
+    }
+
+    pub fn depth(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // 取出所有到期的条目
+    fn take_due(&self) -> Vec<PendingSave> {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        let (due, waiting): (VecDeque<_>, VecDeque<_>) = pending.drain(..).partition(|p| p.next_at <= now);
+        *pending = waiting;
+        due.into()
+    }
+
+    // 失败后按指数退避重新入队，超过重试上限时丢弃
+    fn reschedule(&self, mut item: PendingSave) {
+        item.attempts += 1;
+        if item.attempts >= self.max_attempts {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            tracing::error!(
+                "快照重试 {} 次后仍然失败，丢弃 {} 在 {} 的快照",
+                item.attempts, item.data.proxy_address, item.data.last_updated
+            );
+            return;
+        }
+        item.next_at = Instant::now() + self.base_delay * 2u32.saturating_pow(item.attempts);
+        self.pending.lock().unwrap().push_back(item);
+    }
+}
+
+pub fn spawn(state: SharedState) {
+    if !state.save_retries.enabled() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.save_retries.base_delay);
+        loop {
+            interval.tick().await;
+            for item in state.save_retries.take_due() {
+                let data = &item.data;
+                let timestamp = DateTime::<Utc>::from_timestamp_millis(data.last_updated).unwrap_or_else(Utc::now);
+                // 与正常写入一样，快照和代币余额在同一个事务中补写
+                let balances = crate::snapshot_balances(&state.portfolio, data);
+                match db::save_snapshot_with_balances_at(
+                    &state.db_pool,
+                    &data.proxy_address,
+                    timestamp,
+                    data.portfolio_total,
+                    data.usdc_balance,
+                    data.positions_value,
+                    &balances,
+                ).await {
+                    Ok(_) => {
+                        tracing::info!("重试保存 {} 的快照成功", data.proxy_address);
+                        state.recent_history.push(std::slice::from_ref(data));
+                    }
+                    Err(e) => {
+                        tracing::warn!("重试保存 {} 的快照失败: {}", data.proxy_address, e);
+                        state.errors.record("save_retry", Some(&data.proxy_address), &e);
+                        state.save_retries.reschedule(item);
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,70 @@
+use std::time::Instant;
+
+use axum::extract::State;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::SharedState;
+
+// 链上调用和数据库查询的耗时分布（秒），覆盖从几毫秒到十几秒的范围
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// 安装全局 Prometheus recorder，之后各处用 metrics 宏记录的指标都汇总到这里，由 /metrics 输出
+pub fn install() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_duration_seconds".to_string()), &LATENCY_BUCKETS)
+        .expect("直方图分桶配置无效")
+        .install_recorder()
+        .expect("安装 Prometheus recorder 失败")
+}
+
+pub async fn metrics(State(state): State<SharedState>) -> String {
+    state.metrics.render()
+}
+
+// 单个钱包刷新成功或失败的次数；成功时同时更新该钱包的总价值
+pub fn record_refresh(proxy_address: &str, success: bool, portfolio_total: Option<Decimal>) {
+    let result = if success { "success" } else { "failure" };
+    metrics::counter!("portfolio_refresh_total", "proxy_address" => proxy_address.to_string(), "result" => result)
+        .increment(1);
+    if let Some(total) = portfolio_total {
+        metrics::gauge!("portfolio_total_value", "proxy_address" => proxy_address.to_string()).set(total.to_f64().unwrap_or(0.0));
+    }
+}
+
+pub fn record_rpc_call(call: &'static str, started: Instant, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    metrics::histogram!("rpc_call_duration_seconds", "call" => call, "result" => result)
+        .record(started.elapsed().as_secs_f64());
+}
+
+// data-api 返回非成功状态码的次数，status 为 "none" 表示没有拿到响应（网络错误）
+pub fn record_data_api_error(endpoint: &'static str, status: Option<u16>) {
+    let status = status.map_or_else(|| "none".to_string(), |s| s.to_string());
+    metrics::counter!("data_api_errors_total", "endpoint" => endpoint, "status" => status).increment(1);
+}
+
+pub fn record_fetch_portfolio(started: Instant) {
+    metrics::histogram!("fetch_portfolio_duration_seconds").record(started.elapsed().as_secs_f64());
+}
+
+// 在 db 函数开头创建，函数返回（包括提前返回）时记录耗时
+pub struct DbTimer {
+    query: &'static str,
+    started: Instant,
+}
+
+impl DbTimer {
+    pub fn start(query: &'static str) -> Self {
+        Self { query, started: Instant::now() }
+    }
+}
+
+impl Drop for DbTimer {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        metrics::histogram!("db_query_duration_seconds", "query" => self.query).record(elapsed.as_secs_f64());
+        tracing::debug!(query = self.query, latency_ms = elapsed.as_millis() as u64, "数据库查询完成");
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// 测试用的最小 HTTP/1.1 服务：按请求路径和请求体返回预设的响应，支持 keep-alive，并统计建立的连接数
+pub struct MockServer {
+    pub url: reqwest::Url,
+    connections: Arc<AtomicUsize>,
+}
+
+pub struct MockResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn json(value: serde_json::Value) -> Self {
+        Self { status: 200, content_type: "application/json", body: value.to_string().into_bytes() }
+    }
+
+    pub fn with_status(status: u16, content_type: &'static str, body: &str) -> Self {
+        Self { status, content_type, body: body.as_bytes().to_vec() }
+    }
+
+    // JSON-RPC 成功响应，id 与请求中的一致
+    pub fn rpc_result(request: &serde_json::Value, result: serde_json::Value) -> Self {
+        Self::json(serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    }
+}
+
+impl MockServer {
+    pub async fn start<F>(handler: F) -> MockServer
+    where
+        F: Fn(&str, &[u8]) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap()).parse().unwrap();
+        let handler = Arc::new(handler);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(serve(stream, handler.clone()));
+            }
+        });
+        MockServer { url, connections }
+    }
+
+    // 按 JSON-RPC 方法名和参数返回 result
+    pub async fn rpc<F>(handler: F) -> MockServer
+    where
+        F: Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+    {
+        Self::start(move |_, body| {
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap_or_default();
+            let method = request["method"].as_str().unwrap_or_default();
+            MockResponse::rpc_result(&request, handler(method, &request["params"]))
+        }).await
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+// 绑定后立即释放的端口，连接会被拒绝，用来模拟不可用的节点
+pub async fn dead_url() -> reqwest::Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+    format!("http://{}", address).parse().unwrap()
+}
+
+// eth_call 请求中的 calldata（alloy 放在 input 字段，兼容 data）
+pub fn call_data(params: &serde_json::Value) -> String {
+    let call = &params[0];
+    call["input"].as_str().or(call["data"].as_str()).unwrap_or_default().to_lowercase()
+}
+
+// ABI 编码的 uint256 返回值
+pub fn uint_result(value: u128) -> serde_json::Value {
+    serde_json::json!(format!("0x{:064x}", value))
+}
+
+async fn serve<F>(mut stream: TcpStream, handler: Arc<F>)
+where
+    F: Fn(&str, &[u8]) -> MockResponse,
+{
+    let mut buf = Vec::new();
+    loop {
+        let header_end = loop {
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            if !read_more(&mut stream, &mut buf).await {
+                return;
+            }
+        };
+        let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        let path = head.split_whitespace().nth(1).unwrap_or("/").to_string();
+        let length: usize = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim().eq_ignore_ascii_case("content-length").then(|| value.trim().parse().ok())?
+            })
+            .unwrap_or(0);
+        while buf.len() < header_end + length {
+            if !read_more(&mut stream, &mut buf).await {
+                return;
+            }
+        }
+        let body: Vec<u8> = buf.drain(..header_end + length).skip(header_end).collect();
+
+        let response = handler(&path, &body);
+        let head = format!(
+            "HTTP/1.1 {} MOCK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            response.status, response.content_type, response.body.len()
+        );
+        if stream.write_all(head.as_bytes()).await.is_err() || stream.write_all(&response.body).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> bool {
+    let mut chunk = [0u8; 4096];
+    match stream.read(&mut chunk).await {
+        Ok(0) | Err(_) => false,
+        Ok(n) => {
+            buf.extend_from_slice(&chunk[..n]);
+            true
+        }
+    }
+}
@@ -16,18 +16,20 @@ const WALLET_NAMES: Record<string, string> = {
   '6': '6-1分钱',
 }
 
+// 金额由后端以十进制字符串返回，避免精度损失，展示和求和前再转换成数字
 interface WalletData {
   proxy_address: string
-  usdc_balance: number
-  positions_value: number
-  portfolio_total: number
+  usdc_balance: string
+  positions_value: string
+  portfolio_total: string
   last_updated: number
 }
 
+// 金额以数字字符串返回，使用前转换成 number
 interface HistoryEntry {
   timestamp: number
-  total: number
-  wallets: Record<string, number>
+  total: string
+  wallets: Record<string, string>
 }
 
 interface WalletConfig {
@@ -120,9 +122,9 @@ export default function Home() {
     return () => clearInterval(interval)
   }, [loadCachedData, refreshData])
 
-  const totalPortfolio = wallets.reduce((sum, w) => sum + w.portfolio_total, 0)
-  const totalUsdc = wallets.reduce((sum, w) => sum + w.usdc_balance, 0)
-  const totalPositions = wallets.reduce((sum, w) => sum + w.positions_value, 0)
+  const totalPortfolio = wallets.reduce((sum, w) => sum + Number(w.portfolio_total), 0)
+  const totalUsdc = wallets.reduce((sum, w) => sum + Number(w.usdc_balance), 0)
+  const totalPositions = wallets.reduce((sum, w) => sum + Number(w.positions_value), 0)
 
   const filteredHistory = useMemo(() => {
     const range = TIME_RANGES.find(r => r.label === selectedRange)
@@ -144,7 +146,7 @@ export default function Home() {
       }
       walletConfigs.forEach((config, idx) => {
         if (h.wallets[config.proxy_address] !== undefined) {
-          entry[`wallet_${idx + 1}`] = h.wallets[config.proxy_address]
+          entry[`wallet_${idx + 1}`] = Number(h.wallets[config.proxy_address])
         }
       })
       return entry
@@ -213,18 +215,18 @@ export default function Home() {
                       <span className="text-sm font-semibold">{getWalletName(config.wallet_id)}</span>
                     </div>
                     <div className="text-lg font-bold text-[var(--success)]">
-                      ${wallet.portfolio_total.toFixed(2)}
+                      ${Number(wallet.portfolio_total).toFixed(2)}
                     </div>
                   </div>
                   {/* 第二行：USDC和持仓 */}
                   <div className="grid grid-cols-2 gap-2">
                     <div className="bg-[var(--background)] rounded-lg p-2">
                       <div className="text-[10px] text-[var(--muted)] mb-0.5">USDC</div>
-                      <div className="text-sm font-bold text-[var(--accent)]">${wallet.usdc_balance.toFixed(2)}</div>
+                      <div className="text-sm font-bold text-[var(--accent)]">${Number(wallet.usdc_balance).toFixed(2)}</div>
                     </div>
                     <div className="bg-[var(--background)] rounded-lg p-2">
                       <div className="text-[10px] text-[var(--muted)] mb-0.5">持仓</div>
-                      <div className="text-sm font-bold text-[var(--foreground)]">${wallet.positions_value.toFixed(2)}</div>
+                      <div className="text-sm font-bold text-[var(--foreground)]">${Number(wallet.positions_value).toFixed(2)}</div>
                     </div>
                   </div>
                 </div>